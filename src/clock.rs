@@ -0,0 +1,71 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Where a node's `0xD0` reads the time from, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// Wall-clock time since the graph was loaded. Sleeping blocks the node.
+    Real(Instant),
+    /// Time that passes only when the node sleeps, so a run reads the same
+    /// ticks however it is scheduled and however fast the machine is.
+    Virtual(u64),
+}
+
+impl Clock {
+    pub fn ticks(&self) -> u64 {
+        match self {
+            Self::Real(start) => start.elapsed().as_millis() as u64,
+            Self::Virtual(now) => *now,
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        matches!(self, Self::Virtual(_))
+    }
+
+    /// Sets the clock back to a snapshot taken at `ticks` on a virtual clock
+    /// or, if `virtual_clock` is unset, a real one. A real clock only moves
+    /// forward to `ticks`, since it must never run backwards.
+    pub fn restore(&mut self, ticks: u64, virtual_clock: bool) {
+        if virtual_clock {
+            *self = Self::Virtual(ticks);
+        } else if self.is_virtual() || self.ticks() < ticks {
+            let now = Instant::now();
+            let start = now.checked_sub(Duration::from_millis(ticks));
+            *self = Self::Real(start.unwrap_or(now));
+        }
+    }
+
+    /// Sleeps for `ms`, or on a real clock until `deadline` if that comes
+    /// first. Returns whether the deadline cut the sleep short.
+    pub fn sleep(&mut self, ms: u64, deadline: Option<Instant>) -> bool {
+        match self {
+            Self::Real(_) => {
+                let now = Instant::now();
+                let wake = now + Duration::from_millis(ms);
+                match deadline {
+                    Some(deadline) if deadline < wake => {
+                        thread::sleep(deadline.saturating_duration_since(now));
+                        true
+                    }
+                    _ => {
+                        thread::sleep(wake - now);
+                        false
+                    }
+                }
+            }
+            Self::Virtual(now) => {
+                *now += ms;
+                false
+            }
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::Real(Instant::now())
+    }
+}
@@ -0,0 +1,211 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{node_file::LineEntry, opcode};
+
+/// Which instructions of one node ran, and which way each of its conditional
+/// branches went, collected while it runs with coverage turned on.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// Offset and opcode of every instruction in the code.
+    instructions: Vec<(usize, u8)>,
+    /// Executions of the instruction at each offset.
+    hits: Vec<u64>,
+    /// Outcomes of each `0x50` and `0x51` that ran, by offset.
+    branches: BTreeMap<usize, Branch>,
+    /// Source file and line table, if the node file has debug information.
+    source: Option<(String, Vec<LineEntry>)>,
+}
+
+/// How often a conditional branch jumped and how often it fell through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Coverage {
+    /// Empty coverage for `code`, which must already have been checked with
+    /// [`opcode::find_invalid`]. `source` is the source file and line table
+    /// from the node file's debug section.
+    pub fn new(code: &[u8], source: Option<(String, Vec<LineEntry>)>) -> Self {
+        Self {
+            instructions: opcode::instructions(code).collect(),
+            hits: vec![0; code.len()],
+            branches: BTreeMap::new(),
+            source,
+        }
+    }
+
+    /// Counts one executed instruction, `opcode` at `pc`. If it is a
+    /// conditional branch, `taken` says whether its condition made it jump.
+    pub fn record(&mut self, pc: usize, opcode: u8, taken: bool) {
+        self.hits[pc] += 1;
+
+        if let 0x50 | 0x51 = opcode {
+            let branch = self.branches.entry(pc).or_default();
+            if taken {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+
+    /// Executions of the instruction at `pc`.
+    pub fn hits(&self, pc: usize) -> u64 {
+        self.hits.get(pc).copied().unwrap_or(0)
+    }
+
+    /// Outcomes of the branch at `pc`, if it ran.
+    pub fn branch(&self, pc: usize) -> Option<Branch> {
+        self.branches.get(&pc).copied()
+    }
+
+    /// Instructions executed at least once, and instructions in total.
+    pub fn instructions(&self) -> (usize, usize) {
+        let covered = self
+            .instructions
+            .iter()
+            .filter(|&&(pc, _)| self.hits[pc] > 0);
+        (covered.count(), self.instructions.len())
+    }
+
+    /// Branch outcomes seen at least once, and outcomes in total: two for
+    /// every `0x50` and `0x51` in the code.
+    pub fn branch_outcomes(&self) -> (usize, usize) {
+        let total = self.conditional_branches().count() * 2;
+        let covered = self
+            .branches
+            .values()
+            .map(|b| (b.taken > 0) as usize + (b.not_taken > 0) as usize)
+            .sum();
+        (covered, total)
+    }
+
+    /// Offsets of instructions that never ran, grouped into runs of
+    /// consecutive instructions as `(first, last)`.
+    pub fn uncovered(&self) -> Vec<(usize, usize)> {
+        let mut runs: Vec<(usize, usize)> = vec![];
+        let mut previous_missed = false;
+        for &(pc, _) in &self.instructions {
+            let missed = self.hits[pc] == 0;
+            match runs.last_mut() {
+                Some(run) if missed && previous_missed => run.1 = pc,
+                _ if missed => runs.push((pc, pc)),
+                _ => {}
+            }
+            previous_missed = missed;
+        }
+        runs
+    }
+
+    /// A summary of instruction and branch coverage, followed by the code
+    /// that never ran and the branches that only ever went one way.
+    pub fn report(&self, node: &str) -> String {
+        let mut out = String::new();
+        let (covered, total) = self.instructions();
+        let (outcomes, all_outcomes) = self.branch_outcomes();
+        writeln!(
+            out,
+            "coverage of node {node}: {covered}/{total} instructions ({}), \
+             {outcomes}/{all_outcomes} branch outcomes ({})",
+            percent(covered, total),
+            percent(outcomes, all_outcomes)
+        )
+        .unwrap();
+
+        for (first, last) in self.uncovered() {
+            match self.line(first) {
+                Some(line) => writeln!(out, "  never executed: pc {first}..={last} (line {line})"),
+                None => writeln!(out, "  never executed: pc {first}..={last}"),
+            }
+            .unwrap();
+        }
+        for (pc, branch) in &self.branches {
+            let missing = match (branch.taken, branch.not_taken) {
+                (0, _) => "never taken",
+                (_, 0) => "always taken",
+                _ => continue,
+            };
+            writeln!(out, "  branch at pc {pc}: {missing}").unwrap();
+        }
+
+        out
+    }
+
+    /// The coverage of each source line in LCOV's tracefile format, or `None`
+    /// if the node file has no debug information. A line's count is the
+    /// highest count of the instructions it compiled to.
+    pub fn lcov(&self) -> Option<String> {
+        let (file, _) = self.source.as_ref()?;
+
+        let mut lines: BTreeMap<u32, u64> = BTreeMap::new();
+        for &(pc, _) in &self.instructions {
+            if let Some(line) = self.line(pc) {
+                let count = lines.entry(line).or_default();
+                *count = (*count).max(self.hits[pc]);
+            }
+        }
+
+        let mut out = String::new();
+        writeln!(out, "TN:").unwrap();
+        writeln!(out, "SF:{file}").unwrap();
+
+        let mut found = 0;
+        let mut hit = 0;
+        for pc in self.conditional_branches() {
+            let Some(line) = self.line(pc) else {
+                continue;
+            };
+            let branch = self.branch(pc);
+            for (i, count) in [branch.map(|b| b.taken), branch.map(|b| b.not_taken)]
+                .into_iter()
+                .enumerate()
+            {
+                found += 1;
+                match count {
+                    Some(count) => {
+                        hit += (count > 0) as usize;
+                        writeln!(out, "BRDA:{line},{pc},{i},{count}").unwrap();
+                    }
+                    None => writeln!(out, "BRDA:{line},{pc},{i},-").unwrap(),
+                }
+            }
+        }
+        writeln!(out, "BRF:{found}").unwrap();
+        writeln!(out, "BRH:{hit}").unwrap();
+
+        for (line, count) in &lines {
+            writeln!(out, "DA:{line},{count}").unwrap();
+        }
+        writeln!(out, "LF:{}", lines.len()).unwrap();
+        writeln!(out, "LH:{}", lines.values().filter(|&&c| c > 0).count()).unwrap();
+        writeln!(out, "end_of_record").unwrap();
+
+        Some(out)
+    }
+
+    fn conditional_branches(&self) -> impl Iterator<Item = usize> + '_ {
+        self.instructions
+            .iter()
+            .filter(|&&(_, opcode)| matches!(opcode, 0x50 | 0x51))
+            .map(|&(pc, _)| pc)
+    }
+
+    /// Source line of the instruction at `pc`: that of the last line table
+    /// entry at or before it.
+    fn line(&self, pc: usize) -> Option<u32> {
+        let (_, lines) = self.source.as_ref()?;
+        let i = lines
+            .partition_point(|e| e.pc as usize <= pc)
+            .checked_sub(1)?;
+        Some(lines[i].line)
+    }
+}
+
+fn percent(part: usize, whole: usize) -> String {
+    match whole {
+        0 => "-".to_string(),
+        _ => format!("{:.1}%", part as f64 * 100.0 / whole as f64),
+    }
+}
@@ -0,0 +1,386 @@
+use std::{collections::BTreeMap, ops::Range, time::Instant};
+
+use crate::{error::Error, scheduler::Stop, snapshot::GraphCheckpoint, vm::VirtualMachine};
+
+/// Checkpoints are taken this many instructions apart unless set with
+/// [`Debugger::new`].
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 10_000;
+
+/// A debugger for a whole graph, run by the deterministic scheduler one
+/// instruction at a time.
+///
+/// A point in the run is identified by its time, the number of instructions
+/// the nodes have executed between them. The deterministic scheduler always
+/// interleaves the nodes the same way, so going back to an earlier time is a
+/// matter of restoring the last checkpoint before it and re-executing from
+/// there. Checkpoints are taken every `interval` instructions as the run
+/// first passes them, which bounds the work of any step back to `interval`
+/// instructions.
+///
+/// Output is printed only the first time an instruction runs, and host calls,
+/// file operations and clock readings run again repeat what they did the
+/// first time without calling the host, touching the files or reading the
+/// clock.
+#[derive(Debug)]
+pub struct Debugger {
+    vm: VirtualMachine,
+    start: Vec<usize>,
+    checkpoints: BTreeMap<u64, GraphCheckpoint>,
+    interval: u64,
+    /// Time the debugger was started at, as far back as it can go.
+    origin: u64,
+    /// Furthest time the run has reached.
+    horizon: u64,
+    muted: bool,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+/// Stops a node before it executes the instruction at `pc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub node: usize,
+    pub pc: usize,
+}
+
+/// Stops on any write by `node` to the bytes in `range`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub node: usize,
+    pub range: Range<usize>,
+}
+
+/// An instruction that wrote memory: node `node` executed the instruction at
+/// `pc` at time `time`, taking the run to `time + 1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Write {
+    pub time: u64,
+    pub node: usize,
+    pub pc: usize,
+}
+
+/// Why the debugger stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Ran or went back the number of instructions asked for.
+    Stepped,
+    /// `node` is about to execute an instruction with a breakpoint.
+    Breakpoint { node: usize },
+    /// A watched write. Going forward the debugger stops just after it, and
+    /// going back just before it.
+    Watchpoint(Write),
+    /// The graph has finished.
+    Finished,
+    /// Went back as far as the start of the run.
+    Origin,
+}
+
+/// One instruction executed by [`Debugger::step_once`].
+struct Executed {
+    time: u64,
+    node: usize,
+    pc: usize,
+    stores: Vec<Range<usize>>,
+}
+
+impl Debugger {
+    /// Starts debugging `vm` from its current state, taking a checkpoint every
+    /// `interval` instructions.
+    pub fn new(mut vm: VirtualMachine, interval: u64) -> Result<Self, Error> {
+        vm.check_imports()?;
+        let start = vm.start_nodes()?;
+        vm.keep_input();
+        for node in vm.nodes_mut() {
+            node.track_stores();
+            node.track_outcomes();
+        }
+
+        let origin = vm.steps();
+        let mut checkpoints = BTreeMap::new();
+        checkpoints.insert(origin, vm.checkpoint());
+
+        Ok(Self {
+            vm,
+            start,
+            checkpoints,
+            interval: interval.max(1),
+            origin,
+            horizon: origin,
+            muted: false,
+            breakpoints: vec![],
+            watchpoints: vec![],
+        })
+    }
+
+    pub fn vm(&self) -> &VirtualMachine {
+        &self.vm
+    }
+
+    /// Current time: instructions executed by all nodes between them.
+    pub fn time(&self) -> u64 {
+        self.vm.steps()
+    }
+
+    pub fn add_breakpoint(&mut self, node: &str, pc: usize) -> Result<(), Error> {
+        let node = self.vm.node_index(node)?;
+        self.breakpoints.push(Breakpoint { node, pc });
+
+        Ok(())
+    }
+
+    /// Watches the `len` bytes at `addr` of `node`'s memory.
+    pub fn add_watchpoint(&mut self, node: &str, addr: u32, len: u32) -> Result<(), Error> {
+        let node = self.vm.node_index(node)?;
+        let start = addr as usize;
+        self.watchpoints.push(Watchpoint {
+            node,
+            range: start..start + len.max(1) as usize,
+        });
+
+        Ok(())
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Removes every breakpoint and watchpoint.
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    /// Executes up to `count` instructions, ignoring breakpoints and
+    /// watchpoints.
+    pub fn step(&mut self, count: u64) -> Result<StopReason, Error> {
+        for _ in 0..count {
+            if self.step_once()?.is_none() {
+                return Ok(StopReason::Finished);
+            }
+        }
+
+        Ok(StopReason::Stepped)
+    }
+
+    /// Goes back `count` instructions, or to the start of the run if it is
+    /// fewer than `count` instructions in.
+    pub fn step_back(&mut self, count: u64) -> Result<StopReason, Error> {
+        let time = self.time().saturating_sub(count).max(self.origin);
+        self.goto(time)?;
+
+        if time == self.origin {
+            Ok(StopReason::Origin)
+        } else {
+            Ok(StopReason::Stepped)
+        }
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit or the graph finishes.
+    pub fn continue_forward(&mut self) -> Result<StopReason, Error> {
+        loop {
+            let Some(executed) = self.step_once()? else {
+                return Ok(StopReason::Finished);
+            };
+
+            if let Some(write) = self.watched(&executed) {
+                return Ok(StopReason::Watchpoint(write));
+            }
+            if self.at_breakpoint(executed.node) {
+                return Ok(StopReason::Breakpoint {
+                    node: executed.node,
+                });
+            }
+        }
+    }
+
+    /// Goes back to the most recent point before now where a node reached a
+    /// breakpoint or was about to make a watched write, or to the start of
+    /// the run if there is none.
+    pub fn reverse_continue(&mut self) -> Result<StopReason, Error> {
+        let now = self.time();
+        let found = self.search_back(now, |debugger, executed| {
+            if let Some(write) = debugger.watched(executed) {
+                return Some((write.time, StopReason::Watchpoint(write)));
+            }
+            if debugger.at_breakpoint(executed.node) {
+                let reason = StopReason::Breakpoint {
+                    node: executed.node,
+                };
+                return Some((executed.time + 1, reason));
+            }
+            None
+        })?;
+
+        match found {
+            Some((time, reason)) => {
+                self.goto(time)?;
+                Ok(reason)
+            }
+            None => {
+                self.goto(self.origin)?;
+                Ok(StopReason::Origin)
+            }
+        }
+    }
+
+    /// Finds the last instruction before now that wrote the byte at `addr` of
+    /// `node`'s memory, without moving from the current time.
+    pub fn last_write(&mut self, node: &str, addr: u32) -> Result<Option<Write>, Error> {
+        let node = self.vm.node_index(node)?;
+        let addr = addr as usize;
+
+        let now = self.time();
+        let found = self.search_back(now, |_, executed| {
+            let wrote = executed.node == node && executed.stores.iter().any(|r| r.contains(&addr));
+            wrote.then(|| {
+                let write = Write {
+                    time: executed.time,
+                    node,
+                    pc: executed.pc,
+                };
+                (write.time, write)
+            })
+        })?;
+        self.goto(now)?;
+
+        Ok(found.map(|(_, write)| write))
+    }
+
+    /// Moves to `time`: forwards by running, backwards by re-executing from
+    /// the last checkpoint before it. Stops early if the graph finishes
+    /// first.
+    pub fn goto(&mut self, time: u64) -> Result<(), Error> {
+        if time < self.time() {
+            let (_, checkpoint) = self
+                .checkpoints
+                .range(..=time)
+                .next_back()
+                .expect("there is always a checkpoint at the origin");
+            self.vm.restore(checkpoint.clone())?;
+        }
+
+        while self.time() < time {
+            if self.step_once()?.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs every instruction from the last checkpoint before `before` up to
+    /// `before`, a segment at a time going backwards, and returns the latest
+    /// match that `hit` reports a time before `before` for. Leaves the
+    /// debugger at an unspecified time.
+    fn search_back<T>(
+        &mut self,
+        before: u64,
+        hit: impl Fn(&Self, &Executed) -> Option<(u64, T)>,
+    ) -> Result<Option<(u64, T)>, Error> {
+        let mut end = before;
+        while end > self.origin {
+            let start = *self
+                .checkpoints
+                .range(..end)
+                .next_back()
+                .expect("there is always a checkpoint at the origin")
+                .0;
+            self.goto(start)?;
+
+            let mut found = None;
+            while self.time() < end {
+                let Some(executed) = self.step_once()? else {
+                    break;
+                };
+                match hit(self, &executed) {
+                    Some((time, value)) if time < before => found = Some((time, value)),
+                    _ => {}
+                }
+            }
+            if found.is_some() {
+                return Ok(found);
+            }
+
+            end = start;
+        }
+
+        Ok(None)
+    }
+
+    /// Executes the graph's next instruction, taking a checkpoint if it lands
+    /// on one. Returns `None` if the graph has already finished.
+    fn step_once(&mut self) -> Result<Option<Executed>, Error> {
+        let time = self.time();
+        let muted = time < self.horizon;
+        if muted != self.muted {
+            for node in self.vm.nodes_mut() {
+                node.set_muted(muted);
+            }
+            self.muted = muted;
+        }
+
+        let before: Vec<(u64, usize)> = self
+            .vm
+            .nodes()
+            .iter()
+            .map(|n| (n.steps(), n.pc()))
+            .collect();
+        // The scheduler can report the graph finished right after the
+        // instruction that halts the last node, so look at what ran rather
+        // than at what it returns. The graph's time limit counts per step,
+        // as the time spent waiting on the user is not the graph's.
+        self.vm
+            .run_until(&self.start, Instant::now(), Stop::GraphSteps(time + 1))?;
+        let Some(node) = before
+            .iter()
+            .zip(self.vm.nodes())
+            .position(|(&(steps, _), n)| n.steps() != steps)
+        else {
+            return Ok(None);
+        };
+        let stores = self.vm.nodes_mut()[node].take_stores();
+
+        let now = time + 1;
+        self.horizon = self.horizon.max(now);
+        if (now - self.origin).is_multiple_of(self.interval) {
+            let vm = &self.vm;
+            self.checkpoints
+                .entry(now)
+                .or_insert_with(|| vm.checkpoint());
+        }
+
+        Ok(Some(Executed {
+            time,
+            node,
+            pc: before[node].1,
+            stores,
+        }))
+    }
+
+    fn at_breakpoint(&self, node: usize) -> bool {
+        let pc = self.vm.nodes()[node].pc();
+        self.breakpoints
+            .iter()
+            .any(|b| b.node == node && b.pc == pc)
+    }
+
+    fn watched(&self, executed: &Executed) -> Option<Write> {
+        let hit = self.watchpoints.iter().any(|w| {
+            w.node == executed.node
+                && executed
+                    .stores
+                    .iter()
+                    .any(|r| r.start < w.range.end && w.range.start < r.end)
+        });
+
+        hit.then_some(Write {
+            time: executed.time,
+            node: executed.node,
+            pc: executed.pc,
+        })
+    }
+}
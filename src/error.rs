@@ -0,0 +1,351 @@
+use std::{fmt, io};
+
+use crate::node_file::LoadError;
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    Load {
+        path: String,
+        source: LoadError,
+    },
+    Graph {
+        path: String,
+        message: String,
+    },
+    /// A snapshot or recording could not be read or written.
+    Json {
+        path: String,
+        message: String,
+    },
+    /// A snapshot was taken from different byte code than the node has now.
+    SnapshotMismatch {
+        node: String,
+    },
+    /// A graph checkpoint has no state for one of the graph's nodes.
+    MissingSnapshot {
+        node: String,
+    },
+    /// A recording does not match the graph: its nodes differ, or `node`
+    /// was recorded with different byte code.
+    RecordingMismatch {
+        node: String,
+    },
+    /// A replay did not follow the recording. `event` is the index of the
+    /// first event it could not reproduce.
+    ReplayDiverged {
+        node: String,
+        event: usize,
+    },
+    UnknownNode {
+        node: String,
+    },
+    AmbiguousEntry {
+        nodes: Vec<String>,
+    },
+    /// A node stopped on an instruction it could not execute.
+    Trap {
+        node: String,
+        pc: usize,
+        trap: Trap,
+    },
+    Deadlock {
+        nodes: Vec<String>,
+    },
+    /// A node, or the graph as a whole if `node` is `None`, ran more
+    /// instructions than its limit allows.
+    InstructionLimit {
+        node: Option<String>,
+        limit: u64,
+    },
+    /// A node, or the graph as a whole if `node` is `None`, ran for longer
+    /// than its time limit.
+    TimeLimit {
+        node: Option<String>,
+        limit_ms: u64,
+    },
+    /// A node's memory would have to grow past its limit.
+    MemoryLimit {
+        node: String,
+        requested: u32,
+        limit: u32,
+    },
+    UnknownExport {
+        node: String,
+        function: String,
+    },
+    /// A node imports a host function that has not been registered.
+    MissingImport {
+        node: String,
+        function: String,
+    },
+    /// A host function was registered with a different arity than a node
+    /// imports it with.
+    HostArity {
+        node: String,
+        function: String,
+        imported: u8,
+        registered: u8,
+    },
+    WrongArgumentCount {
+        node: String,
+        function: String,
+        expected: u8,
+        found: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "{path}: {source}"),
+            Self::Load { path, source } => write!(f, "{path}: {source}"),
+            Self::Graph { path, message } => write!(f, "{path}: {message}"),
+            Self::Json { path, message } => write!(f, "{path}: {message}"),
+            Self::SnapshotMismatch { node } => {
+                write!(
+                    f,
+                    "snapshot of node {node} was taken from different byte code"
+                )
+            }
+            Self::MissingSnapshot { node } => write!(f, "checkpoint has no state for node {node}"),
+            Self::RecordingMismatch { node } => {
+                write!(f, "recording does not match node {node} of this graph")
+            }
+            Self::ReplayDiverged { node, event } => write!(
+                f,
+                "replay diverged from the recording at event {event}, on node {node}"
+            ),
+            Self::UnknownNode { node } => write!(f, "no node named {node} in the graph"),
+            Self::AmbiguousEntry { nodes } => write!(
+                f,
+                "graph.json does not name an entry node and the graph has {} nodes ({}); \
+                 set \"entry\" in graph.json or pass --node",
+                nodes.len(),
+                nodes.join(", ")
+            ),
+            Self::Trap { node, pc, trap } => write!(f, "node {node} trapped at pc {pc}: {trap}"),
+            Self::Deadlock { nodes } => write!(
+                f,
+                "deadlock: {} blocked on sends or receives that can never complete",
+                nodes.join(", ")
+            ),
+            Self::InstructionLimit { node, limit } => write!(
+                f,
+                "{} exceeded its limit of {limit} instructions",
+                subject(node)
+            ),
+            Self::TimeLimit { node, limit_ms } => {
+                write!(
+                    f,
+                    "{} exceeded its time limit of {limit_ms} ms",
+                    subject(node)
+                )
+            }
+            Self::MemoryLimit {
+                node,
+                requested,
+                limit,
+            } => write!(
+                f,
+                "node {node} needs {requested} bytes of memory but is limited to {limit}"
+            ),
+            Self::UnknownExport { node, function } => {
+                write!(f, "node {node} does not export a function named {function}")
+            }
+            Self::MissingImport { node, function } => write!(
+                f,
+                "node {node} imports host function {function}, which has not been registered"
+            ),
+            Self::HostArity {
+                node,
+                function,
+                imported,
+                registered,
+            } => write!(
+                f,
+                "node {node} imports host function {function} with {imported} arguments \
+                 but it was registered with {registered}"
+            ),
+            Self::WrongArgumentCount {
+                node,
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{node}::{function} takes {expected} arguments but {found} were given"
+            ),
+        }
+    }
+}
+
+fn subject(node: &Option<String>) -> String {
+    match node {
+        Some(node) => format!("node {node}"),
+        None => "the graph".to_string(),
+    }
+}
+
+impl Error {
+    /// Process exit code for `pndm` when a run fails with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Trap { .. } => 2,
+            Self::Deadlock { .. } => 3,
+            Self::InstructionLimit { .. } => 4,
+            Self::TimeLimit { .. } => 5,
+            Self::MemoryLimit { .. } => 6,
+            _ => 1,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Load { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trap {
+    StackUnderflow,
+    MemoryOutOfBounds {
+        addr: u32,
+        size: usize,
+    },
+    DivisionByZero,
+    InvalidJump {
+        addr: u32,
+    },
+    InvalidOpcode {
+        opcode: u8,
+    },
+    TruncatedInstruction,
+    /// An address computed from an operand and an index does not fit in 32
+    /// bits.
+    AddressOverflow,
+    /// An index into an array declared with `0x80` is not less than its length.
+    IndexOutOfBounds {
+        base: u32,
+        index: u32,
+        len: u32,
+    },
+    /// The index for dimension `dim` of a multi-dimensional array is not less
+    /// than that dimension's length.
+    DimensionOutOfBounds {
+        base: u32,
+        dim: usize,
+        index: u32,
+        len: u32,
+    },
+    /// A multi-dimensional access names an address with no declared shape.
+    UndeclaredArray {
+        base: u32,
+    },
+    /// Multi-dimensional arrays hold 1- or 4-byte elements.
+    InvalidElementSize {
+        size: u32,
+    },
+    /// Memory would have to grow to `requested` bytes, past the node's limit.
+    /// Reported as [`Error::MemoryLimit`] rather than as a trap.
+    MemoryLimit {
+        requested: u32,
+        limit: u32,
+    },
+    /// A sleep would have outlasted the node's time limit, or the graph's if
+    /// `graph` is set. Reported as [`Error::TimeLimit`] rather than as a
+    /// trap.
+    TimeLimit {
+        graph: bool,
+        limit_ms: u64,
+    },
+    NotANeighbor {
+        target: String,
+    },
+    /// A host call was made to an import that no host function has been
+    /// registered for.
+    UnresolvedImport {
+        function: String,
+    },
+    /// A host function returned an error.
+    HostError {
+        function: String,
+        message: String,
+    },
+    /// Reading the input failed for a reason other than reaching its end.
+    InputError {
+        message: String,
+    },
+    /// A node with `limit` files open tried to open another.
+    TooManyOpenFiles {
+        limit: usize,
+    },
+    /// A node has opened a file for every handle a `u32` can hold.
+    FileHandlesExhausted,
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StackUnderflow => write!(f, "stack underflow"),
+            Self::MemoryOutOfBounds { addr, size } => {
+                write!(f, "address {addr} is outside memory of {size} bytes")
+            }
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::InvalidJump { addr } => write!(f, "jump to invalid address {addr}"),
+            Self::InvalidOpcode { opcode } => write!(f, "unrecognized opcode {opcode:#04x}"),
+            Self::TruncatedInstruction => write!(f, "instruction runs past the end of the code"),
+            Self::AddressOverflow => write!(f, "address calculation overflows"),
+            Self::IndexOutOfBounds { base, index, len } => write!(
+                f,
+                "index {index} is out of bounds for the array of length {len} at address {base}"
+            ),
+            Self::DimensionOutOfBounds {
+                base,
+                dim,
+                index,
+                len,
+            } => write!(
+                f,
+                "index {index} is out of bounds for dimension {dim} of length {len} \
+                 of the array at address {base}"
+            ),
+            Self::UndeclaredArray { base } => {
+                write!(f, "no array has been declared at address {base}")
+            }
+            Self::InvalidElementSize { size } => {
+                write!(
+                    f,
+                    "arrays of {size}-byte elements cannot be indexed by dimension"
+                )
+            }
+            Self::MemoryLimit { requested, limit } => write!(
+                f,
+                "needs {requested} bytes of memory but is limited to {limit}"
+            ),
+            Self::TimeLimit { limit_ms, .. } => {
+                write!(f, "sleeps past the time limit of {limit_ms} ms")
+            }
+            Self::NotANeighbor { target } => write!(f, "no edge named {target} to send along"),
+            Self::UnresolvedImport { function } => {
+                write!(f, "host function {function} has not been registered")
+            }
+            Self::HostError { function, message } => {
+                write!(f, "host function {function} failed: {message}")
+            }
+            Self::InputError { message } => write!(f, "could not read input: {message}"),
+            Self::TooManyOpenFiles { limit } => {
+                write!(f, "cannot open more than {limit} files at once")
+            }
+            Self::FileHandlesExhausted => write!(f, "has used up every file handle"),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::error::Trap;
+
+/// Pushed by the file opcodes when the operation succeeded.
+pub const FILE_OK: u32 = 0;
+/// The file does not exist.
+pub const FILE_NOT_FOUND: u32 = 1;
+/// The path is outside every directory granted in `graph.json`, or the
+/// operating system refused access.
+pub const FILE_DENIED: u32 = 2;
+/// The handle is not one of the node's open files.
+pub const FILE_BAD_HANDLE: u32 = 3;
+/// The mode is not one of the `MODE_` constants, or the path is not UTF-8.
+pub const FILE_INVALID: u32 = 4;
+/// Any other I/O error.
+pub const FILE_IO_ERROR: u32 = 5;
+
+/// Opens an existing file for reading.
+pub const MODE_READ: u32 = 0;
+/// Creates a file, or truncates an existing one, for writing.
+pub const MODE_WRITE: u32 = 1;
+/// Creates a file, or opens an existing one, for writing at its end.
+pub const MODE_APPEND: u32 = 2;
+
+/// Most files one node may have open at once.
+pub const MAX_OPEN_FILES: usize = 256;
+
+/// The files one node has open with `0xC0`, and the directories it may open
+/// them in.
+///
+/// A path given to [`open`](Self::open) is resolved against the project
+/// directory, with symbolic links and `..` followed, and must end up inside
+/// one of the directories granted by `files` in `graph.json`. Open files are
+/// not part of a snapshot, and restoring one neither rewinds nor closes them;
+/// the file opcodes a replay or the debugger runs again instead repeat what
+/// they did the first time without touching the files.
+#[derive(Debug, Default)]
+pub struct Files {
+    /// Directory relative paths are resolved against.
+    base: PathBuf,
+    /// Granted directories, canonicalized.
+    dirs: Vec<PathBuf>,
+    open: HashMap<u32, File>,
+    next_handle: u32,
+}
+
+impl Files {
+    /// Grants access to `dirs`, which must already be canonical.
+    pub fn new(base: PathBuf, dirs: Vec<PathBuf>) -> Self {
+        Self {
+            base,
+            dirs,
+            open: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// Opens `path` in `mode` and returns its handle, or a status code.
+    /// Traps rather than opening more than [`MAX_OPEN_FILES`] files, or once
+    /// every handle has been used.
+    pub fn open(&mut self, path: &[u8], mode: u32) -> Result<Result<u32, u32>, Trap> {
+        if self.open.len() >= MAX_OPEN_FILES {
+            return Err(Trap::TooManyOpenFiles {
+                limit: MAX_OPEN_FILES,
+            });
+        }
+        let handle = self
+            .next_handle
+            .checked_add(1)
+            .ok_or(Trap::FileHandlesExhausted)?;
+
+        Ok(self.open_as(handle, path, mode))
+    }
+
+    fn open_as(&mut self, handle: u32, path: &[u8], mode: u32) -> Result<u32, u32> {
+        let path = std::str::from_utf8(path).map_err(|_| FILE_INVALID)?;
+        let mut options = OpenOptions::new();
+        match mode {
+            MODE_READ => options.read(true),
+            MODE_WRITE => options.write(true).create(true).truncate(true),
+            MODE_APPEND => options.append(true).create(true),
+            _ => return Err(FILE_INVALID),
+        };
+
+        let path = self.resolve(Path::new(path)).map_err(|e| status(&e))?;
+        if !self.dirs.iter().any(|dir| path.starts_with(dir)) {
+            return Err(FILE_DENIED);
+        }
+        let file = options.open(&path).map_err(|e| status(&e))?;
+
+        self.next_handle = handle;
+        self.open.insert(handle, file);
+        Ok(handle)
+    }
+
+    /// Reads up to `buf.len()` bytes from `handle`, returning how many were
+    /// read. 0 means the end of the file.
+    pub fn read(&mut self, handle: u32, buf: &mut [u8]) -> Result<usize, u32> {
+        let file = self.open.get_mut(&handle).ok_or(FILE_BAD_HANDLE)?;
+        file.read(buf).map_err(|e| status(&e))
+    }
+
+    /// Writes all of `data` to `handle`.
+    pub fn write(&mut self, handle: u32, data: &[u8]) -> Result<(), u32> {
+        let file = self.open.get_mut(&handle).ok_or(FILE_BAD_HANDLE)?;
+        file.write_all(data).map_err(|e| status(&e))
+    }
+
+    pub fn close(&mut self, handle: u32) -> Result<(), u32> {
+        self.open.remove(&handle).map(drop).ok_or(FILE_BAD_HANDLE)
+    }
+
+    /// Canonical form of `path`. A file that does not exist yet is resolved
+    /// through its parent directory, so it can be created. A dangling
+    /// symbolic link is refused, since creating the file would follow it
+    /// wherever it points.
+    fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = self.base.join(path);
+        match path.canonicalize() {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                if path.symlink_metadata().is_ok() {
+                    return Err(ErrorKind::PermissionDenied.into());
+                }
+                let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                    return Err(e);
+                };
+                Ok(parent.canonicalize()?.join(name))
+            }
+            result => result,
+        }
+    }
+}
+
+fn status(e: &io::Error) -> u32 {
+    match e.kind() {
+        ErrorKind::NotFound => FILE_NOT_FOUND,
+        ErrorKind::PermissionDenied => FILE_DENIED,
+        _ => FILE_IO_ERROR,
+    }
+}
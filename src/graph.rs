@@ -0,0 +1,406 @@
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use serde::{
+    de::{value::MapAccessDeserializer, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+/// Contents of a project's `graph.json`.
+///
+/// The full form describes each node and each edge:
+///
+/// ```json
+/// {
+///     "entry": "Main",
+///     "nodes": {
+///         "Main": { "bytecode": "Main.k", "entry": "main", "params": [3] },
+///         "Worker": { "limits": { "instructions": 1000000 } }
+///     },
+///     "edges": [
+///         { "from": "Main", "to": "Worker", "name": "jobs", "direction": "both" }
+///     ],
+///     "files": ["data"]
+/// }
+/// ```
+///
+/// Node programs can only open files inside the directories listed under
+/// `"files"`, relative to the graph directory, and none if it is left out.
+///
+/// A node may also be given as a list of neighbors, `"Main": ["Worker"]`, and
+/// the original adjacency list, `{"Main": ["Worker"], "Worker": []}`, is
+/// still accepted as a whole file. Neighbor lists become forward edges.
+///
+/// Nodes are kept sorted by name so that node indices do not depend on the
+/// order of keys in the file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphConfig {
+    pub entry: Vec<String>,
+    pub nodes: BTreeMap<String, NodeConfig>,
+    pub edges: Vec<EdgeConfig>,
+    /// Limits on the graph as a whole: instructions across all nodes, and
+    /// wall-clock time for the whole run. Memory can only be limited per
+    /// node.
+    pub limits: Limits,
+    /// Directories node programs may open files in.
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Node file to load, relative to the graph directory. Defaults to
+    /// `<node>.k`.
+    pub bytecode: Option<String>,
+    /// Exported function to start the node in, instead of the node file's
+    /// entry point.
+    pub entry: Option<String>,
+    /// Arguments passed to `entry`.
+    #[serde(default)]
+    pub params: Vec<i32>,
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum number of instructions the node may execute.
+    pub instructions: Option<u64>,
+    /// Maximum wall-clock time the node may run for, in milliseconds.
+    pub time_ms: Option<u64>,
+    /// Maximum size of the node's memory, in bytes. Defaults to
+    /// [`DEFAULT_MEMORY_LIMIT`](crate::vm::DEFAULT_MEMORY_LIMIT).
+    pub memory: Option<u32>,
+}
+
+impl Limits {
+    /// These limits, with any unset limit taken from `defaults`.
+    pub fn or(self, defaults: Limits) -> Limits {
+        Limits {
+            instructions: self.instructions.or(defaults.instructions),
+            time_ms: self.time_ms.or(defaults.time_ms),
+            memory: self.memory.or(defaults.memory),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EdgeConfig {
+    pub from: String,
+    pub to: String,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub direction: Direction,
+    /// Maximum number of undelivered messages on the edge.
+    pub capacity: Option<usize>,
+}
+
+/// Which way messages may travel along an edge, relative to `from` and `to`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Forward,
+    Backward,
+    Both,
+}
+
+impl EdgeConfig {
+    fn forward(from: &str, to: &str) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            name: None,
+            direction: Direction::Forward,
+            capacity: None,
+        }
+    }
+
+    /// The `(sender, receiver)` pairs this edge allows.
+    pub fn links(&self) -> Vec<(&str, &str)> {
+        let (from, to) = (self.from.as_str(), self.to.as_str());
+        match self.direction {
+            Direction::Forward => vec![(from, to)],
+            Direction::Backward => vec![(to, from)],
+            Direction::Both => vec![(from, to), (to, from)],
+        }
+    }
+}
+
+/// A problem with `graph.json`, along with the key it was found under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphError {
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+fn error(key: impl ToString, message: impl Into<String>) -> GraphError {
+    GraphError {
+        key: key.to_string(),
+        message: message.into(),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Graph {
+    #[serde(default)]
+    entry: Option<Entry>,
+    nodes: BTreeMap<String, NodeEntry>,
+    #[serde(default)]
+    edges: Vec<EdgeConfig>,
+    #[serde(default)]
+    limits: Limits,
+    #[serde(default)]
+    files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A node given either as a list of neighbors or as a full [`NodeConfig`].
+enum NodeEntry {
+    Neighbors(Vec<String>),
+    Config(NodeConfig),
+}
+
+impl<'de> Deserialize<'de> for NodeEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NodeEntryVisitor;
+
+        impl<'de> Visitor<'de> for NodeEntryVisitor {
+            type Value = NodeEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a list of neighbors or a node object")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<NodeEntry, A::Error> {
+                let mut neighbors = vec![];
+                while let Some(neighbor) = seq.next_element()? {
+                    neighbors.push(neighbor);
+                }
+                Ok(NodeEntry::Neighbors(neighbors))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<NodeEntry, A::Error> {
+                NodeConfig::deserialize(MapAccessDeserializer::new(map)).map(NodeEntry::Config)
+            }
+        }
+
+        deserializer.deserialize_any(NodeEntryVisitor)
+    }
+}
+
+impl GraphConfig {
+    /// Parses `json` and checks that entries name declared nodes and that
+    /// every edge joins two distinct, declared nodes at most once.
+    pub fn parse(json: &str) -> Result<Self, GraphError> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| error("", e.to_string()))?;
+
+        // Deserialize from the text rather than `value` so that errors carry
+        // line and column numbers.
+        let full_form = value.get("nodes").is_some_and(|n| n.is_object());
+        let (config, keys) = if full_form {
+            let graph: Graph = serde_json::from_str(json).map_err(|e| error("", e.to_string()))?;
+            Self::from_graph(graph)
+        } else {
+            let nodes: BTreeMap<String, Vec<String>> =
+                serde_json::from_str(json).map_err(|e| error("", e.to_string()))?;
+            Self::from_adjacency_list(nodes)
+        };
+
+        config.check(&keys)?;
+
+        Ok(config)
+    }
+
+    /// Builds the config along with the key each edge was declared under.
+    fn from_graph(graph: Graph) -> (Self, Vec<String>) {
+        let mut config = Self {
+            entry: match graph.entry {
+                Some(Entry::One(node)) => vec![node],
+                Some(Entry::Many(nodes)) => nodes,
+                None => vec![],
+            },
+            limits: graph.limits,
+            files: graph.files,
+            ..Self::default()
+        };
+        let mut keys = vec![];
+
+        for (name, node) in graph.nodes {
+            match node {
+                NodeEntry::Neighbors(neighbors) => {
+                    for (i, neighbor) in neighbors.iter().enumerate() {
+                        config.edges.push(EdgeConfig::forward(&name, neighbor));
+                        keys.push(format!("nodes.{name}[{i}]"));
+                    }
+                    config.nodes.insert(name, NodeConfig::default());
+                }
+                NodeEntry::Config(node) => {
+                    config.nodes.insert(name, node);
+                }
+            }
+        }
+        for (i, edge) in graph.edges.into_iter().enumerate() {
+            config.edges.push(edge);
+            keys.push(format!("edges[{i}]"));
+        }
+
+        (config, keys)
+    }
+
+    fn from_adjacency_list(nodes: BTreeMap<String, Vec<String>>) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut keys = vec![];
+
+        for (name, neighbors) in nodes {
+            for (i, neighbor) in neighbors.iter().enumerate() {
+                config.edges.push(EdgeConfig::forward(&name, neighbor));
+                keys.push(format!("{name}[{i}]"));
+            }
+            config.nodes.insert(name, NodeConfig::default());
+        }
+
+        (config, keys)
+    }
+
+    /// Checks a config built in code, reporting edges by their index.
+    pub fn validate(&self) -> Result<(), GraphError> {
+        let keys: Vec<String> = (0..self.edges.len())
+            .map(|i| format!("edges[{i}]"))
+            .collect();
+        self.check(&keys)
+    }
+
+    fn check(&self, keys: &[String]) -> Result<(), GraphError> {
+        if self.limits.memory.is_some() {
+            return Err(error(
+                "limits.memory",
+                "memory can only be limited per node",
+            ));
+        }
+
+        for (i, node) in self.entry.iter().enumerate() {
+            if !self.nodes.contains_key(node) {
+                return Err(error(format!("entry[{i}]"), format!("unknown node {node}")));
+            }
+        }
+
+        for (name, node) in &self.nodes {
+            if node.entry.is_none() && !node.params.is_empty() {
+                return Err(error(
+                    format!("nodes.{name}.params"),
+                    "params are passed to the entry function, but the node has no entry",
+                ));
+            }
+        }
+
+        let mut seen: Vec<(&str, &str, usize)> = vec![];
+        for (i, edge) in self.edges.iter().enumerate() {
+            let key = &keys[i];
+            for node in [&edge.from, &edge.to] {
+                if !self.nodes.contains_key(node) {
+                    return Err(error(key, format!("unknown node {node}")));
+                }
+            }
+            if edge.from == edge.to {
+                return Err(error(key, format!("self-loop on {}", edge.from)));
+            }
+            if edge.capacity == Some(0) {
+                return Err(error(key, "capacity must be at least 1"));
+            }
+
+            for (from, to) in edge.links() {
+                if let Some((_, _, first)) = seen.iter().find(|&&(f, t, _)| (f, t) == (from, to)) {
+                    return Err(error(
+                        key,
+                        format!(
+                            "duplicate edge {from} -> {to}, already declared by {}",
+                            keys[*first]
+                        ),
+                    ));
+                }
+                seen.push((from, to, i));
+            }
+
+            if let Some(name) = &edge.name {
+                // Sends resolve a target by edge name or by node name, so a
+                // name that is another node's would be ambiguous.
+                let targets_name = edge.links().iter().any(|&(_, to)| to == name);
+                if self.nodes.contains_key(name) && !targets_name {
+                    return Err(error(
+                        key,
+                        format!("edge name {name} is the name of another node"),
+                    ));
+                }
+                let clash = self.edges[..i]
+                    .iter()
+                    .any(|e| e.name.as_ref() == Some(name) && shares_node(e, edge));
+                if clash {
+                    return Err(error(key, format!("duplicate edge name {name}")));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Path of the node file for `node`, relative to the graph directory.
+    pub fn bytecode_path(&self, node: &str) -> String {
+        self.nodes
+            .get(node)
+            .and_then(|n| n.bytecode.clone())
+            .unwrap_or_else(|| format!("{node}.k"))
+    }
+
+    /// Checks that every node's file and every granted directory exists
+    /// under `dir`.
+    pub fn check_files(&self, dir: &Path) -> Result<(), GraphError> {
+        for (i, granted) in self.files.iter().enumerate() {
+            if !dir.join(granted).is_dir() {
+                return Err(error(
+                    format!("files[{i}]"),
+                    format!("{} is not a directory", dir.join(granted).display()),
+                ));
+            }
+        }
+
+        for node in self.nodes.keys() {
+            let file = self.bytecode_path(node);
+            if !dir.join(&file).is_file() {
+                return Err(error(
+                    format!("nodes.{node}.bytecode"),
+                    format!("{} does not exist", dir.join(&file).display()),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn shares_node(a: &EdgeConfig, b: &EdgeConfig) -> bool {
+    [&a.from, &a.to]
+        .iter()
+        .any(|n| **n == b.from || **n == b.to)
+}
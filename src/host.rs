@@ -0,0 +1,50 @@
+use std::{fmt, sync::Arc};
+
+type Callback = dyn Fn(&[u32]) -> Result<u32, String> + Send + Sync;
+
+/// A Rust function that node programs call with `0xB0`, registered with
+/// [`VirtualMachine::register_host_fn`](crate::vm::VirtualMachine::register_host_fn).
+///
+/// It is given its arguments in the order the program pushed them and
+/// returns the value to push in their place, or a message that stops the node
+/// with a trap.
+#[derive(Clone)]
+pub struct HostFn {
+    name: String,
+    arity: u8,
+    f: Arc<Callback>,
+}
+
+impl HostFn {
+    pub fn new<F>(name: &str, arity: u8, f: F) -> Self
+    where
+        F: Fn(&[u32]) -> Result<u32, String> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity,
+            f: Arc::new(f),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> u8 {
+        self.arity
+    }
+
+    pub fn call(&self, args: &[u32]) -> Result<u32, String> {
+        (self.f)(args)
+    }
+}
+
+impl fmt::Debug for HostFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostFn")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader},
+    sync::{Arc, Mutex},
+};
+
+/// Pushed by the input opcodes above the value they read: it was read.
+pub const READ_OK: u32 = 0;
+/// The input ended before anything could be read.
+pub const READ_EOF: u32 = 1;
+/// The next token is not a number of the type asked for. It is consumed.
+pub const READ_INVALID: u32 = 2;
+
+/// The input the `0x94`–`0x97` opcodes read, shared by every node of a graph.
+/// Nodes consume it in the order their input instructions run, which makes
+/// those instructions events.
+///
+/// A checkpoint only needs the number of bytes consumed. Restoring one in a
+/// fresh graph reads the input up to that point again, but going back in the
+/// same graph needs the bytes since then: those from the point passed to
+/// [`keep_from`](Self::keep_from) on are kept once consumed, and any before
+/// it are dropped, so that a long run does not hold on to all its input.
+#[derive(Clone)]
+pub struct Input(Arc<Mutex<Tape>>);
+
+struct Tape {
+    source: Box<dyn BufRead + Send>,
+    /// Bytes taken from `source` and not yet dropped, the first of them at
+    /// offset `start` of the input.
+    read: Vec<u8>,
+    start: usize,
+    /// Offset of the next byte to consume.
+    position: usize,
+    /// Earliest offset [`Input::seek`] may go back to, if any.
+    kept: Option<usize>,
+}
+
+impl Input {
+    pub fn new(source: impl BufRead + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(Tape {
+            source: Box::new(source),
+            read: vec![],
+            start: 0,
+            position: 0,
+            kept: None,
+        })))
+    }
+
+    pub fn stdin() -> Self {
+        Self::new(BufReader::new(io::stdin()))
+    }
+
+    /// Bytes consumed so far.
+    pub fn position(&self) -> u64 {
+        self.0.lock().unwrap().position as u64
+    }
+
+    /// Keeps every byte from `position` on once it is consumed, so that
+    /// [`seek`](Self::seek) can go back to it and [`consumed`](Self::consumed)
+    /// returns it.
+    pub fn keep_from(&self, position: u64) {
+        let mut tape = self.0.lock().unwrap();
+        let position = (position as usize).max(tape.start);
+        tape.kept = Some(tape.kept.map_or(position, |kept| kept.min(position)));
+    }
+
+    /// Rewinds or fast-forwards to `position`, taking bytes from the source
+    /// if it has not got that far yet. Stops at the end of the input, and
+    /// going back stops at the earliest byte still kept.
+    pub fn seek(&self, position: u64) {
+        let position = position as usize;
+        let mut tape = self.0.lock().unwrap();
+        while tape.end() < position {
+            tape.position = tape.end();
+            match tape.pull() {
+                Ok(Some(_)) => {}
+                _ => break,
+            }
+        }
+        tape.position = position.clamp(tape.start, tape.end());
+    }
+
+    /// Every byte consumed since the point passed to
+    /// [`keep_from`](Self::keep_from).
+    pub fn consumed(&self) -> Vec<u8> {
+        let tape = self.0.lock().unwrap();
+        let from = tape.kept.unwrap_or(tape.position).min(tape.position);
+        tape.read[from - tape.start..tape.position - tape.start].to_vec()
+    }
+
+    /// The next byte, or `None` at the end of the input.
+    pub fn read_byte(&self) -> io::Result<Option<u8>> {
+        self.0.lock().unwrap().next()
+    }
+
+    /// The next run of non-whitespace bytes after any whitespace, or `None`
+    /// if only whitespace is left. The whitespace after it is not consumed.
+    pub fn read_token(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut tape = self.0.lock().unwrap();
+        while tape.peek()?.is_some_and(|b| b.is_ascii_whitespace()) {
+            tape.position += 1;
+        }
+
+        let mut token = vec![];
+        while let Some(byte) = tape.peek()?.filter(|b| !b.is_ascii_whitespace()) {
+            token.push(byte);
+            tape.position += 1;
+        }
+        Ok((!token.is_empty()).then_some(token))
+    }
+
+    /// The rest of the current line without its `\n` or `\r\n`, or `None` at
+    /// the end of the input.
+    pub fn read_line(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut tape = self.0.lock().unwrap();
+        if tape.peek()?.is_none() {
+            return Ok(None);
+        }
+
+        let mut line = vec![];
+        while let Some(byte) = tape.next()? {
+            if byte == b'\n' {
+                break;
+            }
+            line.push(byte);
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+}
+
+impl Tape {
+    /// Offset just past the last byte taken from the source.
+    fn end(&self) -> usize {
+        self.start + self.read.len()
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        match self.read.get(self.position - self.start) {
+            Some(&byte) => Ok(Some(byte)),
+            None => self.pull(),
+        }
+    }
+
+    fn next(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.peek()?;
+        if byte.is_some() {
+            self.position += 1;
+        }
+        Ok(byte)
+    }
+
+    /// Takes one more byte from the source, without consuming it. Consumed
+    /// bytes that need not be kept are dropped first.
+    fn pull(&mut self) -> io::Result<Option<u8>> {
+        let keep = self.kept.unwrap_or(self.position).min(self.position);
+        if keep > self.start {
+            self.read.drain(..keep - self.start);
+            self.start = keep;
+        }
+
+        let Some(&byte) = self.source.fill_buf()?.first() else {
+            return Ok(None);
+        };
+        self.source.consume(1);
+        self.read.push(byte);
+        Ok(Some(byte))
+    }
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Input")
+            .field("position", &self.position())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,17 @@
+pub mod clock;
+pub mod coverage;
+pub mod debugger;
+pub mod error;
+pub mod files;
+pub mod graph;
+pub mod host;
+pub mod input;
+pub mod message;
+pub mod node_file;
+pub mod opcode;
+pub mod profile;
+pub mod random;
+pub mod record;
+pub mod scheduler;
+pub mod snapshot;
+pub mod vm;
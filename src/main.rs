@@ -1,9 +1,14 @@
-mod vm;
-
 use clap::{Parser, Subcommand};
-use std::io::Write;
+use pndm::{
+    debugger::{Debugger, StopReason, DEFAULT_CHECKPOINT_INTERVAL},
+    graph::Limits,
+    record::RunLog,
+    snapshot::SnapshotFile,
+    vm::VirtualMachine,
+};
+use std::io::{BufRead, Write};
 use std::process::Command;
-use vm::VirtualMachine;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -11,14 +16,141 @@ struct Args {
     command: ArgsCommand,
 }
 
+// Parsed once at startup, so the size of `Run` does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 enum ArgsCommand {
-    ///
     New {
         path: String,
     },
     Build,
-    Run,
+    /// Run the compiled project in `comp`
+    Run {
+        /// Call an exported function instead of running the node from its
+        /// entry point, as `<node>::<function>`
+        #[arg(long, value_parser = parse_entry, conflicts_with = "node")]
+        entry: Option<EntryPoint>,
+        /// Run this node instead of the entry named in graph.json
+        #[arg(long)]
+        node: Option<String>,
+        /// Skip checking array indices against declared array lengths
+        #[arg(long)]
+        no_bounds_checks: bool,
+        /// Run nodes in parallel on this many worker threads
+        #[arg(long, default_value_t = 1)]
+        threads: usize,
+        /// Stop the run after this many instructions across all nodes
+        #[arg(long)]
+        max_instructions: Option<u64>,
+        /// Stop the run after this many milliseconds
+        #[arg(long)]
+        timeout_ms: Option<u64>,
+        /// Instruction limit for nodes that do not set their own in graph.json
+        #[arg(long)]
+        node_max_instructions: Option<u64>,
+        /// Time limit in milliseconds for nodes that do not set their own in
+        /// graph.json
+        #[arg(long)]
+        node_timeout_ms: Option<u64>,
+        /// Memory limit in bytes for nodes that do not set their own in
+        /// graph.json
+        #[arg(long)]
+        node_max_memory: Option<u32>,
+        /// Write a snapshot of the start node once it has executed this many
+        /// instructions
+        #[arg(long, conflicts_with = "entry")]
+        snapshot_at: Option<u64>,
+        /// Where `--snapshot-at` writes the snapshot
+        #[arg(long, default_value = "snapshot.json", requires = "snapshot_at")]
+        snapshot_file: String,
+        /// Write a checkpoint of the whole graph once its nodes have executed
+        /// this many instructions between them
+        #[arg(long, conflicts_with_all = ["entry", "snapshot_at"])]
+        checkpoint_at: Option<u64>,
+        /// Where `--checkpoint-at` writes the checkpoint
+        #[arg(long, default_value = "checkpoint.json", requires = "checkpoint_at")]
+        checkpoint_file: String,
+        /// Record the order of messages, output and other events to this
+        /// file, for `pndm replay`
+        #[arg(long, conflicts_with = "entry")]
+        record: Option<String>,
+        /// Count the instructions each node executes by function, opcode and
+        /// offset, and print the counts after the run
+        #[arg(long)]
+        profile: bool,
+        /// Write the profile as collapsed stacks, for flame graph tools
+        #[arg(long)]
+        profile_stacks: Option<String>,
+        /// Read the program's input from this file instead of stdin
+        #[arg(long)]
+        input: Option<String>,
+        /// Seed for the random number opcodes; the same seed gives the same
+        /// numbers. Defaults to one taken from the system clock
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Give every node a clock that only advances when it sleeps, instead
+        /// of the wall clock
+        #[arg(long)]
+        virtual_clock: bool,
+        /// Arguments passed to the `--entry` function
+        #[arg(allow_negative_numbers = true, requires = "entry")]
+        args: Vec<i32>,
+    },
+    /// Rerun a run recorded with `run --record` on one thread, in the same
+    /// order
+    Replay {
+        log: String,
+    },
+    /// Resume a node or a whole graph from a file written by `run
+    /// --snapshot-at` or `run --checkpoint-at`
+    Resume {
+        snapshot: String,
+    },
+    /// Run the compiled project and report which instructions and branches
+    /// of each node were executed
+    Coverage {
+        /// Run this node instead of the entry named in graph.json
+        #[arg(long)]
+        node: Option<String>,
+        /// Also write line and branch coverage of each node with debug
+        /// information to this file, in LCOV format
+        #[arg(long)]
+        lcov: Option<String>,
+        /// Read the program's input from this file instead of stdin
+        #[arg(long)]
+        input: Option<String>,
+    },
+    /// Step through the compiled project, forwards and backwards, with
+    /// breakpoints and watchpoints
+    Debug {
+        /// Debug this node instead of the entry named in graph.json
+        #[arg(long)]
+        node: Option<String>,
+        /// Instructions between the checkpoints used to step backwards; lower
+        /// values step back faster and use more memory
+        #[arg(long, default_value_t = DEFAULT_CHECKPOINT_INTERVAL)]
+        checkpoint_every: u64,
+        /// Read the program's input from this file. The debugger reads its
+        /// commands from stdin, so without it the program gets no input
+        #[arg(long)]
+        input: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct EntryPoint {
+    node: String,
+    function: String,
+}
+
+fn parse_entry(s: &str) -> Result<EntryPoint, String> {
+    match s.split_once("::") {
+        Some((node, function)) if !node.is_empty() && !function.is_empty() => Ok(EntryPoint {
+            node: node.to_string(),
+            function: function.to_string(),
+        }),
+        _ => Err(format!("expected <node>::<function>, found {s}")),
+    }
 }
 
 fn main() {
@@ -32,10 +164,8 @@ fn main() {
             let mut file = std::fs::File::create(format!("{}/src/main.krm", path.clone()))
                 .expect("could not create main file");
 
-            file.write(
-                format!("node Main {{\n\nfn main() -> int {{\n\treturn 0;\n}}\n\n}}").as_bytes(),
-            )
-            .expect("could not write to file");
+            file.write_all(b"node Main {\n\nfn main() -> int {\n\treturn 0;\n}\n\n}")
+                .expect("could not write to file");
         }
         ArgsCommand::Build => {
             let o = Command::new("C:/Users/mihir/projects/karma/target/release/karma.exe")
@@ -46,9 +176,344 @@ fn main() {
             println!("{:?}", String::from_utf8(o.stdout));
             println!("{:?}", String::from_utf8(o.stderr));
         }
-        ArgsCommand::Run => {
-            let mut vm = VirtualMachine::new("comp");
-            vm.execute();
+        ArgsCommand::Run {
+            entry,
+            node,
+            no_bounds_checks,
+            threads,
+            max_instructions,
+            timeout_ms,
+            node_max_instructions,
+            node_timeout_ms,
+            node_max_memory,
+            snapshot_at,
+            snapshot_file,
+            checkpoint_at,
+            checkpoint_file,
+            record,
+            profile,
+            profile_stacks,
+            input,
+            seed,
+            virtual_clock,
+            args,
+        } => {
+            let mut vm = VirtualMachine::with_limits(
+                "comp",
+                Limits {
+                    instructions: max_instructions,
+                    time_ms: timeout_ms,
+                    memory: None,
+                },
+                Limits {
+                    instructions: node_max_instructions,
+                    time_ms: node_timeout_ms,
+                    memory: node_max_memory,
+                },
+            )
+            .unwrap_or_else(|e| exit_with(e));
+            if let Some(path) = input {
+                read_input_from(&mut vm, path);
+            }
+            vm.set_seed(seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64)
+            }));
+            if virtual_clock {
+                vm.use_virtual_clock();
+            }
+            vm.set_threads(threads);
+            vm.set_bounds_checks(!no_bounds_checks);
+            if let Some(node) = node {
+                vm.set_entry_node(&node).unwrap_or_else(|e| exit_with(e));
+            }
+            if record.is_some() {
+                vm.record();
+            }
+            if profile || profile_stacks.is_some() {
+                vm.profile();
+            }
+
+            match entry {
+                Some(EntryPoint { node, function }) => {
+                    let args: Vec<u32> = args.iter().map(|&a| a as u32).collect();
+                    let result = vm.call(&node, &function, &args);
+                    write_profile(&vm, profile, profile_stacks);
+                    match result {
+                        Ok(Some(value)) => println!("{}", value as i32),
+                        Ok(None) => {}
+                        Err(e) => exit_with(e),
+                    }
+                }
+                None => {
+                    let result = match (snapshot_at, checkpoint_at) {
+                        (Some(steps), _) => vm.execute_with_snapshot(steps, &snapshot_file),
+                        (_, Some(steps)) => vm.execute_with_checkpoint(steps, &checkpoint_file),
+                        (None, None) => vm.execute(),
+                    };
+                    // A run that failed is the one most worth replaying, so
+                    // write the log either way.
+                    if let (Some(path), Some(log)) = (record, vm.recording()) {
+                        log.write(&path).unwrap_or_else(|e| exit_with(e));
+                    }
+                    write_profile(&vm, profile, profile_stacks);
+                    result.unwrap_or_else(|e| exit_with(e));
+                }
+            }
+        }
+        ArgsCommand::Replay { log } => {
+            let log = RunLog::read(&log).unwrap_or_else(|e| exit_with(e));
+            let mut vm = VirtualMachine::new("comp").unwrap_or_else(|e| exit_with(e));
+            vm.replay(&log).unwrap_or_else(|e| exit_with(e));
+        }
+        ArgsCommand::Resume { snapshot } => {
+            let snapshot = SnapshotFile::read(&snapshot).unwrap_or_else(|e| exit_with(e));
+            let mut vm = VirtualMachine::new("comp").unwrap_or_else(|e| exit_with(e));
+            match snapshot {
+                SnapshotFile::Graph(checkpoint) => vm.restore(checkpoint),
+                SnapshotFile::Node(snapshot) => vm
+                    .set_entry_node(&snapshot.node)
+                    .and_then(|_| vm.restore_node(snapshot)),
+            }
+            .unwrap_or_else(|e| exit_with(e));
+            vm.execute().unwrap_or_else(|e| exit_with(e));
+        }
+        ArgsCommand::Coverage { node, lcov, input } => {
+            let mut vm = VirtualMachine::new("comp").unwrap_or_else(|e| exit_with(e));
+            if let Some(path) = input {
+                read_input_from(&mut vm, path);
+            }
+            if let Some(node) = node {
+                vm.set_entry_node(&node).unwrap_or_else(|e| exit_with(e));
+            }
+            vm.collect_coverage();
+            let result = vm.execute();
+
+            for (node, coverage) in vm.coverage() {
+                print!("{}", coverage.report(node));
+            }
+            if let Some(path) = lcov {
+                let mut tracefile = String::new();
+                for (node, coverage) in vm.coverage() {
+                    match coverage.lcov() {
+                        Some(records) => tracefile.push_str(&records),
+                        None => {
+                            eprintln!("node {node} has no debug information; left out of {path}")
+                        }
+                    }
+                }
+                std::fs::write(&path, tracefile)
+                    .unwrap_or_else(|source| exit_with(pndm::error::Error::Io { path, source }));
+            }
+            result.unwrap_or_else(|e| exit_with(e));
         }
+        ArgsCommand::Debug {
+            node,
+            checkpoint_every,
+            input,
+        } => {
+            let mut vm = VirtualMachine::new("comp").unwrap_or_else(|e| exit_with(e));
+            match input {
+                Some(path) => read_input_from(&mut vm, path),
+                None => vm.set_input(std::io::empty()),
+            }
+            if let Some(node) = node {
+                vm.set_entry_node(&node).unwrap_or_else(|e| exit_with(e));
+            }
+            let debugger = Debugger::new(vm, checkpoint_every).unwrap_or_else(|e| exit_with(e));
+            debug(debugger);
+        }
+    }
+}
+
+/// Makes the input opcodes read from the file at `path`.
+fn read_input_from(vm: &mut VirtualMachine, path: String) {
+    match std::fs::File::open(&path) {
+        Ok(file) => vm.set_input(std::io::BufReader::new(file)),
+        Err(source) => exit_with(pndm::error::Error::Io { path, source }),
     }
 }
+
+/// Prints the profile of every node that ran to stderr, and writes collapsed
+/// stacks for all of them to `stacks`.
+fn write_profile(vm: &VirtualMachine, print: bool, stacks: Option<String>) {
+    let profiles = vm.profiles();
+    let ran = profiles.iter().filter(|(_, p)| p.instructions() > 0);
+
+    if print {
+        for (node, profile) in ran.clone() {
+            eprintln!("{}", profile.report(node));
+        }
+    }
+    if let Some(path) = stacks {
+        let collapsed: String = ran.map(|(node, p)| p.collapsed(node)).collect();
+        std::fs::write(&path, collapsed)
+            .unwrap_or_else(|source| exit_with(pndm::error::Error::Io { path, source }));
+    }
+}
+
+const DEBUG_HELP: &str = "\
+commands:
+  step, s [n]              execute n instructions (default 1)
+  back, b [n]              go back n instructions (default 1)
+  continue, c              run to the next breakpoint or watchpoint
+  reverse, rc              go back to the previous breakpoint or watchpoint
+  goto <time>              go to the point where <time> instructions have run
+  break [node] <pc>        stop when a node is about to execute <pc>
+  watch [node] <addr> [n]  stop when a node writes the n bytes at <addr> (default 4)
+  delete                   remove every breakpoint and watchpoint
+  writer [node] <addr>     show which instruction last wrote <addr>
+  print, p [node]          show a node's stack, memory and typed symbols
+  quit, q";
+
+/// Reads debugger commands from stdin until `quit` or end of input. `node`
+/// arguments default to the first start node.
+fn debug(mut debugger: Debugger) {
+    let start = debugger.vm().start_nodes().unwrap_or_default();
+    let default_node = debugger.vm().nodes()[start[0]].name().to_string();
+
+    println!("{DEBUG_HELP}");
+    show_location(&debugger);
+
+    let stdin = std::io::stdin();
+    loop {
+        print!("(pndm) ");
+        std::io::stdout().flush().ok();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            continue;
+        };
+
+        // Node names are never numbers, so a leading number is the first
+        // numeric argument.
+        let (node, numbers) = match args.first() {
+            Some(first) if first.parse::<u64>().is_err() => (first.to_string(), &args[1..]),
+            _ => (default_node.clone(), args),
+        };
+        let numbers: Result<Vec<u64>, _> = numbers.iter().map(|n| n.parse::<u64>()).collect();
+        let Ok(numbers) = numbers else {
+            println!("expected numbers, found {}", args.join(" "));
+            continue;
+        };
+        let number = |i: usize| numbers.get(i).copied();
+
+        let result = match (command, number(0)) {
+            ("step" | "s", n) => debugger.step(n.unwrap_or(1)).map(Some),
+            ("back" | "b", n) => debugger.step_back(n.unwrap_or(1)).map(Some),
+            ("continue" | "c", _) => debugger.continue_forward().map(Some),
+            ("reverse" | "rc", _) => debugger.reverse_continue().map(Some),
+            ("goto", Some(time)) => debugger.goto(time).map(|_| {
+                let finished = debugger.time() < time;
+                Some(if finished {
+                    StopReason::Finished
+                } else {
+                    StopReason::Stepped
+                })
+            }),
+            ("break", Some(pc)) => debugger.add_breakpoint(&node, pc as usize).map(|_| None),
+            ("watch", Some(addr)) => {
+                let len = number(1).unwrap_or(4) as u32;
+                debugger
+                    .add_watchpoint(&node, addr as u32, len)
+                    .map(|_| None)
+            }
+            ("delete", _) => {
+                debugger.clear();
+                Ok(None)
+            }
+            ("writer", Some(addr)) => match debugger.last_write(&node, addr as u32) {
+                Ok(Some(write)) => {
+                    println!(
+                        "{node} byte {addr} was last written by pc {} at time {}",
+                        write.pc, write.time
+                    );
+                    Ok(None)
+                }
+                Ok(None) => {
+                    println!("{node} byte {addr} has not been written since the start");
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            },
+            ("print" | "p", _) => match debugger.vm().node(&node) {
+                Some(machine) => {
+                    println!("{node} at pc {}", machine.pc());
+                    machine.print_state();
+                    Ok(None)
+                }
+                None => {
+                    println!("no node named {node}");
+                    Ok(None)
+                }
+            },
+            ("quit" | "q", _) => break,
+            ("help" | "h", _) => {
+                println!("{DEBUG_HELP}");
+                Ok(None)
+            }
+            _ => {
+                println!("unknown command {}; try help", line.trim());
+                Ok(None)
+            }
+        };
+
+        match result {
+            Ok(Some(reason)) => {
+                // Program output has no trailing newline of its own.
+                println!();
+                show_stop(&debugger, reason);
+                show_location(&debugger);
+            }
+            Ok(None) => {}
+            // A trap leaves the debugger just before the instruction that
+            // caused it, so it can still step back from there.
+            Err(e) => {
+                println!();
+                println!("error: {e}");
+                show_location(&debugger);
+            }
+        }
+    }
+}
+
+fn show_stop(debugger: &Debugger, reason: StopReason) {
+    let name = |node: usize| debugger.vm().nodes()[node].name();
+    match reason {
+        StopReason::Stepped => {}
+        StopReason::Breakpoint { node } => println!("{} reached a breakpoint", name(node)),
+        StopReason::Watchpoint(write) => println!(
+            "{} wrote watched memory with the instruction at pc {}",
+            name(write.node),
+            write.pc
+        ),
+        StopReason::Finished => println!("the program has finished"),
+        StopReason::Origin => println!("at the start of the run"),
+    }
+}
+
+fn show_location(debugger: &Debugger) {
+    let nodes: Vec<String> = debugger
+        .vm()
+        .nodes()
+        .iter()
+        .map(|n| {
+            if n.is_halted() {
+                format!("{} halted", n.name())
+            } else {
+                format!("{} pc {}", n.name(), n.pc())
+            }
+        })
+        .collect();
+    println!("time {}: {}", debugger.time(), nodes.join(", "));
+}
+
+fn exit_with(error: pndm::error::Error) -> ! {
+    eprintln!("error: {error}");
+    std::process::exit(error.exit_code());
+}
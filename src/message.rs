@@ -0,0 +1,44 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A value sent from one node to another along an edge in `graph.json`.
+/// `from` is the index of the sending node in the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub from: usize,
+    pub value: u32,
+}
+
+/// Messages waiting to be received by a node, in arrival order. Shared with
+/// every node that has an edge to it.
+pub type Mailbox = Arc<Mutex<VecDeque<Message>>>;
+
+/// An outgoing edge of a node. Programs address it by the edge's name or by
+/// the name of the receiving node, `peer`.
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub name: Option<String>,
+    pub peer: String,
+    pub node: usize,
+    pub mailbox: Mailbox,
+    /// Maximum number of undelivered messages from this sender; sending to a
+    /// full link blocks until the receiver catches up.
+    pub capacity: Option<usize>,
+}
+
+impl Link {
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.as_deref() == Some(name) || self.peer == name
+    }
+
+    /// Whether `sender` has `capacity` messages waiting in `queue`, the
+    /// receiver's locked mailbox.
+    pub fn is_full(&self, queue: &VecDeque<Message>, sender: usize) -> bool {
+        self.capacity
+            .is_some_and(|capacity| queue.iter().filter(|m| m.from == sender).count() >= capacity)
+    }
+}
@@ -0,0 +1,802 @@
+use std::fmt;
+
+use crate::opcode;
+
+/// Bytes every versioned node file starts with.
+pub const MAGIC: [u8; 4] = *b"KRMA";
+
+/// The newest format version this VM can load.
+pub const VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 16;
+const SECTION_ENTRY_LEN: usize = 12;
+
+/// A compiled node, as stored in a `.k` file.
+///
+/// Versioned files are laid out big-endian as a 16 byte header
+///
+/// ```text
+/// magic: [u8; 4] | version: u16 | flags: u16 | entry: u32 | sections: u16 | reserved: u16
+/// ```
+///
+/// followed by one 12 byte table entry per section
+///
+/// ```text
+/// kind: u8 | reserved: [u8; 3] | offset: u32 | length: u32
+/// ```
+///
+/// where `offset` is measured from the start of the file. Files that do not
+/// start with [`MAGIC`] are treated as legacy headerless files whose every
+/// byte is code.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeFile {
+    pub version: u16,
+    pub flags: u16,
+    pub entry: u32,
+    pub code: Vec<u8>,
+    pub rodata: Vec<u8>,
+    /// Size of the node's `memory` at load time.
+    pub memory_size: u32,
+    /// Initial contents of `memory`, starting at address 0. Never longer than
+    /// `memory_size`; the rest of memory is zeroed. The data section stores
+    /// `memory_size: u32` followed by these bytes.
+    pub data: Vec<u8>,
+    pub strings: Vec<String>,
+    pub symbols: Vec<Symbol>,
+    pub debug: Option<DebugInfo>,
+    /// Struct layouts, used to show memory by field name.
+    pub types: Vec<TypeLayout>,
+    /// Which layout each typed data symbol has.
+    pub symbol_types: Vec<SymbolType>,
+    /// Host functions the code calls with `0xB0`, by index.
+    pub imports: Vec<Import>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SectionKind {
+    Code = 1,
+    RoData = 2,
+    Strings = 3,
+    Symbols = 4,
+    Debug = 5,
+    Data = 6,
+    Types = 7,
+    Imports = 8,
+}
+
+impl SectionKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            1 => Some(Self::Code),
+            2 => Some(Self::RoData),
+            3 => Some(Self::Strings),
+            4 => Some(Self::Symbols),
+            5 => Some(Self::Debug),
+            6 => Some(Self::Data),
+            7 => Some(Self::Types),
+            8 => Some(Self::Imports),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Code => "code",
+            Self::RoData => "rodata",
+            Self::Strings => "strings",
+            Self::Symbols => "symbols",
+            Self::Debug => "debug",
+            Self::Data => "data",
+            Self::Types => "types",
+            Self::Imports => "imports",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SymbolKind {
+    Function = 0,
+    Data = 1,
+}
+
+/// A symbol table entry. `name` indexes the string section, `address` is a
+/// code offset for functions and a memory address for data. Exported
+/// functions can be called by name from the host, taking `arity` arguments.
+///
+/// Encoded as `name: u32 | kind: u8 | flags: u8 | arity: u8 | reserved: u8 |
+/// address: u32 | size: u32`, where bit 0 of `flags` marks the symbol as
+/// exported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: u32,
+    pub kind: SymbolKind,
+    pub exported: bool,
+    pub arity: u8,
+    pub address: u32,
+    pub size: u32,
+}
+
+const SYMBOL_EXPORTED: u8 = 0x01;
+
+/// Source-level debug information: the source file (a string index) and a
+/// table mapping code offsets to source lines, sorted by `pc`.
+///
+/// Encoded as `file: u32 | count: u32` followed by `count` pairs of
+/// `pc: u32 | line: u32`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub file: u32,
+    pub lines: Vec<LineEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+    pub pc: u32,
+    pub line: u32,
+}
+
+/// The layout of a struct type: its name (a string index), its size in bytes
+/// and its fields. A struct field may only use a layout that comes earlier in
+/// the types section, so layouts cannot contain themselves.
+///
+/// The types section is encoded as `count: u32` followed by each layout as
+/// `name: u32 | size: u32 | fields: u32` and then each field as
+/// `name: u32 | kind: u8 | reserved: [u8; 3] | offset: u32 | type: u32`, where
+/// `type` is a layout index for struct fields and 0 otherwise. The layouts are
+/// followed by `count: u32` and that many [`SymbolType`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeLayout {
+    pub name: u32,
+    pub size: u32,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: u32,
+    pub kind: FieldKind,
+    /// Offset from the start of the struct, in bytes.
+    pub offset: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Int,
+    Float,
+    Bool,
+    Char,
+    /// A nested struct, by layout index.
+    Struct(u32),
+}
+
+impl FieldKind {
+    /// Size of a field of this kind, in bytes. Struct fields must refer to a
+    /// layout in `types`.
+    pub fn size(self, types: &[TypeLayout]) -> u32 {
+        match self {
+            Self::Int | Self::Float => 4,
+            Self::Bool | Self::Char => 1,
+            Self::Struct(layout) => types[layout as usize].size,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Self::Int => 0,
+            Self::Float => 1,
+            Self::Bool => 2,
+            Self::Char => 3,
+            Self::Struct(_) => 4,
+        }
+    }
+}
+
+/// Gives the data symbol at index `symbol` the layout at index `layout`.
+/// Encoded as `symbol: u32 | layout: u32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolType {
+    pub symbol: u32,
+    pub layout: u32,
+}
+
+/// A host function the node calls, which the embedder must register under
+/// `name` (a string index) before the node runs.
+///
+/// The imports section is encoded as `count: u32` followed by each import as
+/// `name: u32 | arity: u8 | reserved: [u8; 3]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub name: u32,
+    pub arity: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// The file has no header and does not decode as legacy byte code either.
+    NotANodeFile {
+        offset: usize,
+    },
+    UnsupportedVersion {
+        found: u16,
+    },
+    Truncated {
+        what: String,
+    },
+    UnknownSection {
+        kind: u8,
+    },
+    DuplicateSection {
+        kind: SectionKind,
+    },
+    MissingCode,
+    InvalidInstruction {
+        offset: usize,
+    },
+    EntryOutOfBounds {
+        entry: u32,
+    },
+    InvalidString {
+        index: usize,
+    },
+    InvalidStringIndex {
+        index: u32,
+    },
+    InvalidSymbolKind {
+        kind: u8,
+    },
+    DataTooLarge {
+        len: usize,
+        memory_size: u32,
+    },
+    SymbolOutOfBounds {
+        name: String,
+        address: u32,
+    },
+    InvalidFieldKind {
+        kind: u8,
+    },
+    /// A struct field or symbol refers to a layout that does not exist, or
+    /// that does not come before the struct using it.
+    InvalidLayoutIndex {
+        index: u32,
+    },
+    /// A symbol type refers to a symbol that does not exist or is not data.
+    InvalidDataSymbol {
+        index: u32,
+    },
+    FieldOutOfBounds {
+        layout: String,
+        field: String,
+    },
+    /// A host call refers to an import that does not exist.
+    InvalidImportIndex {
+        index: u32,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotANodeFile { offset } => write!(
+                f,
+                "not a node file (no header, and byte {offset} is not a valid instruction)"
+            ),
+            Self::UnsupportedVersion { found } => write!(
+                f,
+                "unsupported node file version {found} (this VM supports versions up to {VERSION})"
+            ),
+            Self::Truncated { what } => write!(f, "file is truncated in {what}"),
+            Self::UnknownSection { kind } => write!(f, "unknown section kind {kind}"),
+            Self::DuplicateSection { kind } => write!(f, "duplicate {kind} section"),
+            Self::MissingCode => write!(f, "missing code section"),
+            Self::InvalidInstruction { offset } => {
+                write!(
+                    f,
+                    "invalid or truncated instruction at code offset {offset}"
+                )
+            }
+            Self::EntryOutOfBounds { entry } => {
+                write!(f, "entry point {entry} is outside the code section")
+            }
+            Self::InvalidString { index } => write!(f, "string {index} is not valid UTF-8"),
+            Self::InvalidStringIndex { index } => {
+                write!(f, "reference to missing string {index}")
+            }
+            Self::InvalidSymbolKind { kind } => write!(f, "unknown symbol kind {kind}"),
+            Self::DataTooLarge { len, memory_size } => write!(
+                f,
+                "data section holds {len} bytes but memory is only {memory_size} bytes"
+            ),
+            Self::SymbolOutOfBounds { name, address } => write!(
+                f,
+                "function {name} starts at {address}, outside the code section"
+            ),
+            Self::InvalidFieldKind { kind } => write!(f, "unknown field kind {kind}"),
+            Self::InvalidLayoutIndex { index } => write!(f, "reference to missing layout {index}"),
+            Self::InvalidDataSymbol { index } => {
+                write!(f, "symbol {index} does not exist or is not a data symbol")
+            }
+            Self::FieldOutOfBounds { layout, field } => {
+                write!(f, "field {field} runs past the end of struct {layout}")
+            }
+            Self::InvalidImportIndex { index } => write!(f, "reference to missing import {index}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl NodeFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, LoadError> {
+        if !bytes.starts_with(&MAGIC) {
+            return Self::parse_legacy(bytes);
+        }
+
+        let mut header = Reader::new(bytes, "header");
+        header.skip(MAGIC.len())?;
+        let version = header.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(LoadError::UnsupportedVersion { found: version });
+        }
+        let flags = header.u16()?;
+        let entry = header.u32()?;
+        let section_count = header.u16()? as usize;
+        header.skip(2)?;
+
+        let mut table = Reader::new(bytes, "section table");
+        table.skip(HEADER_LEN)?;
+
+        let mut file = Self {
+            version,
+            flags,
+            entry,
+            ..Self::default()
+        };
+        let mut seen = vec![];
+        let mut code = None;
+
+        for _ in 0..section_count {
+            let kind = table.u8()?;
+            table.skip(3)?;
+            let offset = table.u32()? as usize;
+            let len = table.u32()? as usize;
+
+            let kind = SectionKind::from_u8(kind).ok_or(LoadError::UnknownSection { kind })?;
+            if seen.contains(&kind) {
+                return Err(LoadError::DuplicateSection { kind });
+            }
+            seen.push(kind);
+
+            let data = offset
+                .checked_add(len)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| LoadError::Truncated {
+                    what: format!("{kind} section"),
+                })?;
+
+            match kind {
+                SectionKind::Code => code = Some(data.to_vec()),
+                SectionKind::RoData => file.rodata = data.to_vec(),
+                SectionKind::Strings => file.strings = parse_strings(data)?,
+                SectionKind::Symbols => file.symbols = parse_symbols(data)?,
+                SectionKind::Debug => file.debug = Some(parse_debug(data)?),
+                SectionKind::Types => (file.types, file.symbol_types) = parse_types(data)?,
+                SectionKind::Imports => file.imports = parse_imports(data)?,
+                SectionKind::Data => {
+                    let mut r = Reader::new(data, "data section");
+                    file.memory_size = r.u32()?;
+                    file.data = data[4..].to_vec();
+                    if file.data.len() > file.memory_size as usize {
+                        return Err(LoadError::DataTooLarge {
+                            len: file.data.len(),
+                            memory_size: file.memory_size,
+                        });
+                    }
+                }
+            }
+        }
+
+        file.code = code.ok_or(LoadError::MissingCode)?;
+        if let Some(offset) = opcode::find_invalid(&file.code) {
+            return Err(LoadError::InvalidInstruction { offset });
+        }
+        if file.entry as usize > file.code.len() {
+            return Err(LoadError::EntryOutOfBounds { entry: file.entry });
+        }
+        file.check_string_refs()?;
+        file.check_types()?;
+        file.check_imports()?;
+        for symbol in &file.symbols {
+            if symbol.kind == SymbolKind::Function && symbol.address as usize >= file.code.len() {
+                return Err(LoadError::SymbolOutOfBounds {
+                    name: file.symbol_name(symbol).to_string(),
+                    address: symbol.address,
+                });
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Legacy files are raw opcodes starting at byte 0. They are only accepted
+    /// if the whole file decodes as a sequence of known instructions, which
+    /// keeps JSON, text, and truncated downloads from being executed. With no
+    /// string or imports section, they can neither send nor call the host.
+    fn parse_legacy(bytes: &[u8]) -> Result<Self, LoadError> {
+        if let Some(offset) = opcode::find_invalid(bytes) {
+            return Err(LoadError::NotANodeFile { offset });
+        }
+
+        let file = Self {
+            code: bytes.to_vec(),
+            ..Self::default()
+        };
+        file.check_string_refs()?;
+        file.check_imports()?;
+
+        Ok(file)
+    }
+
+    fn check_string_refs(&self) -> Result<(), LoadError> {
+        let refs = self
+            .symbols
+            .iter()
+            .map(|s| s.name)
+            .chain(self.debug.iter().map(|d| d.file))
+            .chain(self.imports.iter().map(|i| i.name))
+            .chain(
+                self.types
+                    .iter()
+                    .flat_map(|t| std::iter::once(t.name).chain(t.fields.iter().map(|f| f.name))),
+            )
+            .chain(
+                opcode::instructions(&self.code)
+                    .filter(|&(_, op)| op == 0xA0)
+                    .map(|(pc, _)| opcode::operand_u32(&self.code, pc)),
+            );
+
+        for index in refs {
+            if index as usize >= self.strings.len() {
+                return Err(LoadError::InvalidStringIndex { index });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_types(&self) -> Result<(), LoadError> {
+        for (index, layout) in self.types.iter().enumerate() {
+            for field in &layout.fields {
+                let size = match field.kind {
+                    FieldKind::Struct(nested) if nested as usize >= index => {
+                        return Err(LoadError::InvalidLayoutIndex { index: nested });
+                    }
+                    kind => kind.size(&self.types),
+                };
+                if field
+                    .offset
+                    .checked_add(size)
+                    .is_none_or(|end| end > layout.size)
+                {
+                    return Err(LoadError::FieldOutOfBounds {
+                        layout: self.strings[layout.name as usize].clone(),
+                        field: self.strings[field.name as usize].clone(),
+                    });
+                }
+            }
+        }
+
+        for ty in &self.symbol_types {
+            match self.symbols.get(ty.symbol as usize) {
+                Some(symbol) if symbol.kind == SymbolKind::Data => {}
+                _ => return Err(LoadError::InvalidDataSymbol { index: ty.symbol }),
+            }
+            if ty.layout as usize >= self.types.len() {
+                return Err(LoadError::InvalidLayoutIndex { index: ty.layout });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_imports(&self) -> Result<(), LoadError> {
+        let calls = opcode::instructions(&self.code)
+            .filter(|&(_, op)| op == 0xB0)
+            .map(|(pc, _)| opcode::operand_u32(&self.code, pc));
+
+        for index in calls {
+            if index as usize >= self.imports.len() {
+                return Err(LoadError::InvalidImportIndex { index });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == 0
+    }
+
+    /// Name of `symbol`, looked up in the string section.
+    pub fn symbol_name(&self, symbol: &Symbol) -> &str {
+        &self.strings[symbol.name as usize]
+    }
+
+    /// Encodes the file in the current format version.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sections: Vec<(SectionKind, Vec<u8>)> =
+            vec![(SectionKind::Code, self.code.clone())];
+        if !self.rodata.is_empty() {
+            sections.push((SectionKind::RoData, self.rodata.clone()));
+        }
+        if self.memory_size > 0 || !self.data.is_empty() {
+            let mut data = self.memory_size.to_be_bytes().to_vec();
+            data.extend_from_slice(&self.data);
+            sections.push((SectionKind::Data, data));
+        }
+        if !self.strings.is_empty() {
+            sections.push((SectionKind::Strings, encode_strings(&self.strings)));
+        }
+        if !self.symbols.is_empty() {
+            sections.push((SectionKind::Symbols, encode_symbols(&self.symbols)));
+        }
+        if let Some(debug) = &self.debug {
+            sections.push((SectionKind::Debug, encode_debug(debug)));
+        }
+        if !self.types.is_empty() {
+            sections.push((
+                SectionKind::Types,
+                encode_types(&self.types, &self.symbol_types),
+            ));
+        }
+        if !self.imports.is_empty() {
+            sections.push((SectionKind::Imports, encode_imports(&self.imports)));
+        }
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+        bytes.extend_from_slice(&self.flags.to_be_bytes());
+        bytes.extend_from_slice(&self.entry.to_be_bytes());
+        bytes.extend_from_slice(&(sections.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&[0; 2]);
+
+        let mut offset = HEADER_LEN + SECTION_ENTRY_LEN * sections.len();
+        for (kind, data) in &sections {
+            bytes.push(*kind as u8);
+            bytes.extend_from_slice(&[0; 3]);
+            bytes.extend_from_slice(&(offset as u32).to_be_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        for (_, data) in sections {
+            bytes.extend(data);
+        }
+
+        bytes
+    }
+}
+
+fn parse_strings(data: &[u8]) -> Result<Vec<String>, LoadError> {
+    let mut r = Reader::new(data, "strings section");
+    let count = r.u32()?;
+    let mut strings = vec![];
+    for index in 0..count as usize {
+        let len = r.u32()? as usize;
+        let bytes = r.bytes(len)?;
+        let s =
+            String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::InvalidString { index })?;
+        strings.push(s);
+    }
+    Ok(strings)
+}
+
+fn encode_strings(strings: &[String]) -> Vec<u8> {
+    let mut data = (strings.len() as u32).to_be_bytes().to_vec();
+    for s in strings {
+        data.extend_from_slice(&(s.len() as u32).to_be_bytes());
+        data.extend_from_slice(s.as_bytes());
+    }
+    data
+}
+
+fn parse_symbols(data: &[u8]) -> Result<Vec<Symbol>, LoadError> {
+    let mut r = Reader::new(data, "symbols section");
+    let count = r.u32()?;
+    let mut symbols = vec![];
+    for _ in 0..count {
+        let name = r.u32()?;
+        let kind = match r.u8()? {
+            0 => SymbolKind::Function,
+            1 => SymbolKind::Data,
+            kind => return Err(LoadError::InvalidSymbolKind { kind }),
+        };
+        let flags = r.u8()?;
+        let arity = r.u8()?;
+        r.skip(1)?;
+        let address = r.u32()?;
+        let size = r.u32()?;
+        symbols.push(Symbol {
+            name,
+            kind,
+            exported: flags & SYMBOL_EXPORTED != 0,
+            arity,
+            address,
+            size,
+        });
+    }
+    Ok(symbols)
+}
+
+fn encode_symbols(symbols: &[Symbol]) -> Vec<u8> {
+    let mut data = (symbols.len() as u32).to_be_bytes().to_vec();
+    for symbol in symbols {
+        data.extend_from_slice(&symbol.name.to_be_bytes());
+        data.push(symbol.kind as u8);
+        data.push(if symbol.exported { SYMBOL_EXPORTED } else { 0 });
+        data.push(symbol.arity);
+        data.push(0);
+        data.extend_from_slice(&symbol.address.to_be_bytes());
+        data.extend_from_slice(&symbol.size.to_be_bytes());
+    }
+    data
+}
+
+fn parse_debug(data: &[u8]) -> Result<DebugInfo, LoadError> {
+    let mut r = Reader::new(data, "debug section");
+    let file = r.u32()?;
+    let count = r.u32()?;
+    let mut lines = vec![];
+    for _ in 0..count {
+        let pc = r.u32()?;
+        let line = r.u32()?;
+        lines.push(LineEntry { pc, line });
+    }
+    Ok(DebugInfo { file, lines })
+}
+
+fn encode_debug(debug: &DebugInfo) -> Vec<u8> {
+    let mut data = debug.file.to_be_bytes().to_vec();
+    data.extend_from_slice(&(debug.lines.len() as u32).to_be_bytes());
+    for entry in &debug.lines {
+        data.extend_from_slice(&entry.pc.to_be_bytes());
+        data.extend_from_slice(&entry.line.to_be_bytes());
+    }
+    data
+}
+
+fn parse_types(data: &[u8]) -> Result<(Vec<TypeLayout>, Vec<SymbolType>), LoadError> {
+    let mut r = Reader::new(data, "types section");
+    let count = r.u32()?;
+    let mut types = vec![];
+    for _ in 0..count {
+        let name = r.u32()?;
+        let size = r.u32()?;
+        let field_count = r.u32()?;
+        let mut fields = vec![];
+        for _ in 0..field_count {
+            let name = r.u32()?;
+            let kind = r.u8()?;
+            r.skip(3)?;
+            let offset = r.u32()?;
+            let layout = r.u32()?;
+            let kind = match kind {
+                0 => FieldKind::Int,
+                1 => FieldKind::Float,
+                2 => FieldKind::Bool,
+                3 => FieldKind::Char,
+                4 => FieldKind::Struct(layout),
+                kind => return Err(LoadError::InvalidFieldKind { kind }),
+            };
+            fields.push(Field { name, kind, offset });
+        }
+        types.push(TypeLayout { name, size, fields });
+    }
+
+    let count = r.u32()?;
+    let mut symbol_types = vec![];
+    for _ in 0..count {
+        let symbol = r.u32()?;
+        let layout = r.u32()?;
+        symbol_types.push(SymbolType { symbol, layout });
+    }
+
+    Ok((types, symbol_types))
+}
+
+fn encode_types(types: &[TypeLayout], symbol_types: &[SymbolType]) -> Vec<u8> {
+    let mut data = (types.len() as u32).to_be_bytes().to_vec();
+    for layout in types {
+        data.extend_from_slice(&layout.name.to_be_bytes());
+        data.extend_from_slice(&layout.size.to_be_bytes());
+        data.extend_from_slice(&(layout.fields.len() as u32).to_be_bytes());
+        for field in &layout.fields {
+            data.extend_from_slice(&field.name.to_be_bytes());
+            data.push(field.kind.code());
+            data.extend_from_slice(&[0; 3]);
+            data.extend_from_slice(&field.offset.to_be_bytes());
+            let layout = match field.kind {
+                FieldKind::Struct(layout) => layout,
+                _ => 0,
+            };
+            data.extend_from_slice(&layout.to_be_bytes());
+        }
+    }
+    data.extend_from_slice(&(symbol_types.len() as u32).to_be_bytes());
+    for ty in symbol_types {
+        data.extend_from_slice(&ty.symbol.to_be_bytes());
+        data.extend_from_slice(&ty.layout.to_be_bytes());
+    }
+    data
+}
+
+fn parse_imports(data: &[u8]) -> Result<Vec<Import>, LoadError> {
+    let mut r = Reader::new(data, "imports section");
+    let count = r.u32()?;
+    let mut imports = vec![];
+    for _ in 0..count {
+        let name = r.u32()?;
+        let arity = r.u8()?;
+        r.skip(3)?;
+        imports.push(Import { name, arity });
+    }
+    Ok(imports)
+}
+
+fn encode_imports(imports: &[Import]) -> Vec<u8> {
+    let mut data = (imports.len() as u32).to_be_bytes().to_vec();
+    for import in imports {
+        data.extend_from_slice(&import.name.to_be_bytes());
+        data.push(import.arity);
+        data.extend_from_slice(&[0; 3]);
+    }
+    data
+}
+
+/// Big-endian cursor that reports which part of the file ran out of bytes.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], what: &'static str) -> Self {
+        Self { data, pos: 0, what }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| LoadError::Truncated {
+                what: self.what.to_string(),
+            })?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), LoadError> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
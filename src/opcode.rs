@@ -0,0 +1,73 @@
+/// Number of operand bytes that follow `opcode` in the instruction stream, or
+/// `None` if the VM does not recognize the opcode.
+pub fn operand_len(opcode: u8) -> Option<usize> {
+    match opcode {
+        0x12 | 0x26 | 0x27 => Some(0),
+        0x14 | 0x15 => Some(1),
+        0x10 | 0x11 | 0x13 => Some(4),
+        0x20..=0x25 | 0x28..=0x2A | 0x2C..=0x2E => Some(4),
+        0x30..=0x39 => Some(0),
+        0x40..=0x43 => Some(8),
+        0x44..=0x47 => Some(4),
+        0x48..=0x4A => Some(0),
+        0x50 | 0x51 | 0x5A => Some(4),
+        0x52..=0x59 | 0x5B..=0x64 => Some(0),
+        0x80 => Some(9),
+        0x81 => Some(6),
+        0x82..=0x8B => Some(4),
+        0x90..=0x97 => Some(0),
+        0xA0 => Some(4),
+        0xA1 => Some(0),
+        0xB0 => Some(4),
+        0xC0..=0xC3 => Some(0),
+        0xD0..=0xD3 => Some(0),
+        _ => None,
+    }
+}
+
+/// Whether `opcode` interacts with other nodes or the host. Nodes only affect
+/// each other through these instructions, so recording the order they run
+/// in across the graph is enough to replay a run.
+pub fn is_event(opcode: u8) -> bool {
+    matches!(opcode, 0x90..=0x97 | 0xA0 | 0xA1 | 0xB0 | 0xC0..=0xC3 | 0xD0 | 0xD1)
+}
+
+/// Whether `opcode` is an event whose result depends on the world outside
+/// the graph, so that a replay must be given its [`Outcome`] rather than run
+/// it again.
+///
+/// [`Outcome`]: crate::record::Outcome
+pub fn has_outcome(opcode: u8) -> bool {
+    matches!(opcode, 0xB0 | 0xC0..=0xC3 | 0xD0)
+}
+
+/// Walks `code` instruction by instruction and returns the offset of the first
+/// byte that is not a recognized opcode, or of an instruction whose operands
+/// run past the end of `code`.
+pub fn find_invalid(code: &[u8]) -> Option<usize> {
+    let mut pc = 0;
+    while pc < code.len() {
+        match operand_len(code[pc]) {
+            Some(len) if pc + len < code.len() => pc += len + 1,
+            _ => return Some(pc),
+        }
+    }
+    None
+}
+
+/// Iterates over `(offset, opcode)` for each instruction in `code`, which must
+/// already have been checked with [`find_invalid`].
+pub fn instructions(code: &[u8]) -> impl Iterator<Item = (usize, u8)> + '_ {
+    let mut pc = 0;
+    std::iter::from_fn(move || {
+        let opcode = *code.get(pc)?;
+        let offset = pc;
+        pc += 1 + operand_len(opcode).unwrap_or(0);
+        Some((offset, opcode))
+    })
+}
+
+/// Reads the big-endian `u32` operand of the instruction at `pc`.
+pub fn operand_u32(code: &[u8], pc: usize) -> u32 {
+    u32::from_be_bytes([code[pc + 1], code[pc + 2], code[pc + 3], code[pc + 4]])
+}
@@ -0,0 +1,355 @@
+use std::{
+    collections::{HashMap, LinkedList},
+    fmt::Write,
+};
+
+/// Rows shown in each of the per-instruction and loop tables of a report.
+const TOP: usize = 10;
+
+/// Instruction counts for one node, collected while it runs with profiling
+/// turned on.
+///
+/// Counts are kept per opcode, per instruction and per call stack. Calls are
+/// tracked on a shadow stack: a `0x5A` jump to the first instruction of a
+/// function in the symbol table is a call if it leaves its return address,
+/// the offset of the instruction after the jump, just below the function's
+/// arguments. `0x5B` and `0x64` return. Without a symbol table every
+/// instruction is counted against the node itself.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    opcodes: Vec<u64>,
+    pcs: Vec<u64>,
+    /// Times each backward jump or branch was taken, by `(from, to)`.
+    back_edges: HashMap<(usize, usize), u64>,
+    /// Function names and code ranges, sorted by address.
+    functions: Vec<Function>,
+    /// Every call stack seen so far, as a tree. Frame 0 is the node itself.
+    frames: Vec<Frame>,
+    children: HashMap<(usize, usize), usize>,
+    /// Frames of the current call stack, innermost last.
+    stack: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    name: String,
+    address: usize,
+    /// Size in bytes, or 0 if the symbol table does not say.
+    size: usize,
+    arity: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    parent: usize,
+    function: Option<usize>,
+    /// Instructions executed with this frame innermost.
+    count: u64,
+}
+
+/// Self and total instruction counts of one function, as reported by
+/// [`Profile::functions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCounts {
+    pub name: String,
+    /// Instructions executed in the function itself.
+    pub self_count: u64,
+    /// Instructions executed in the function and everything it called.
+    pub total: u64,
+}
+
+/// A loop, found as the backward jump or branch at `end` that goes back to
+/// `start`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub start: usize,
+    pub end: usize,
+    /// Times the jump back was taken.
+    pub iterations: u64,
+    /// Instructions executed between `start` and `end`, including any inner
+    /// loops.
+    pub instructions: u64,
+}
+
+impl Profile {
+    /// An empty profile for `code_len` bytes of code whose functions are
+    /// `(name, address, size, arity)`.
+    pub fn new(code_len: usize, functions: Vec<(String, usize, usize, usize)>) -> Self {
+        let mut functions: Vec<Function> = functions
+            .into_iter()
+            .map(|(name, address, size, arity)| Function {
+                name,
+                address,
+                size,
+                arity,
+            })
+            .collect();
+        functions.sort_by_key(|f| f.address);
+
+        Self {
+            opcodes: vec![0; 256],
+            pcs: vec![0; code_len],
+            back_edges: HashMap::new(),
+            functions,
+            frames: vec![Frame {
+                parent: 0,
+                function: None,
+                count: 0,
+            }],
+            children: HashMap::new(),
+            stack: vec![],
+        }
+    }
+
+    /// Counts one executed instruction, `opcode` at `pc`, after which the
+    /// machine moved on to `next` with `stack` as its operand stack.
+    pub fn record(&mut self, pc: usize, opcode: u8, next: usize, stack: &LinkedList<u32>) {
+        if self.stack.is_empty() {
+            // The first instruction tells which function the node starts in.
+            let root = self.function_at(pc).map_or(0, |f| self.child(0, f));
+            self.stack.push(root);
+        }
+
+        self.opcodes[opcode as usize] += 1;
+        self.pcs[pc] += 1;
+        let current = *self.stack.last().unwrap();
+        self.frames[current].count += 1;
+
+        match opcode {
+            0x5A if self.is_call(pc, next, stack) => {
+                let function = self.function_at(next).unwrap();
+                let frame = self.child(current, function);
+                self.stack.push(frame);
+            }
+            0x5B | 0x64 if self.stack.len() > 1 => {
+                self.stack.pop();
+            }
+            0x50 | 0x51 | 0x5A if next <= pc => {
+                *self.back_edges.entry((pc, next)).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Instructions counted in total.
+    pub fn instructions(&self) -> u64 {
+        self.opcodes.iter().sum()
+    }
+
+    /// Executions of each opcode that ran at least once, most frequent first.
+    pub fn opcodes(&self) -> Vec<(u8, u64)> {
+        let mut opcodes: Vec<(u8, u64)> = (0..=255u8)
+            .map(|op| (op, self.opcodes[op as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        opcodes
+    }
+
+    /// Executions of each instruction that ran at least once, by offset, most
+    /// frequent first.
+    pub fn pcs(&self) -> Vec<(usize, u64)> {
+        let mut pcs: Vec<(usize, u64)> = self
+            .pcs
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(pc, &count)| (pc, count))
+            .collect();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pcs
+    }
+
+    /// Self and total counts of every function that ran, highest total
+    /// first. Instructions outside any known function are counted against
+    /// `<node>`. A recursive function's total counts each instruction once.
+    pub fn functions(&self) -> Vec<FunctionCounts> {
+        let mut counts: HashMap<Option<usize>, (u64, u64)> = HashMap::new();
+        for (id, frame) in self.frames.iter().enumerate() {
+            if frame.count == 0 {
+                continue;
+            }
+            counts.entry(frame.function).or_default().0 += frame.count;
+
+            // Frame 0 is on every path, but only counts towards `<node>` for
+            // instructions run outside any function.
+            let path = match id {
+                0 => vec![0],
+                _ => self.path(id).split_off(1),
+            };
+            let mut seen = vec![];
+            for function in path.into_iter().map(|f| self.frames[f].function) {
+                if !seen.contains(&function) {
+                    counts.entry(function).or_default().1 += frame.count;
+                    seen.push(function);
+                }
+            }
+        }
+
+        let mut functions: Vec<FunctionCounts> = counts
+            .into_iter()
+            .map(|(function, (self_count, total))| FunctionCounts {
+                name: self.function_name(function).to_string(),
+                self_count,
+                total,
+            })
+            .collect();
+        functions.sort_by(|a, b| b.total.cmp(&a.total).then(a.name.cmp(&b.name)));
+        functions
+    }
+
+    /// Every loop that ran, most instructions first.
+    pub fn loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = self
+            .back_edges
+            .iter()
+            .map(|(&(end, start), &iterations)| Loop {
+                start,
+                end,
+                iterations,
+                instructions: self.pcs[start..=end].iter().sum(),
+            })
+            .collect();
+        loops.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then(a.start.cmp(&b.start))
+        });
+        loops
+    }
+
+    /// The profile as tables of functions, opcodes, the hottest instructions
+    /// and the hottest loops.
+    pub fn report(&self, node: &str) -> String {
+        let mut out = String::new();
+        let total = self.instructions();
+        let percent = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+
+        writeln!(out, "profile of node {node}: {total} instructions").unwrap();
+
+        writeln!(out, "\n{:<24} {:>20} {:>20}", "function", "self", "total").unwrap();
+        for f in self.functions() {
+            writeln!(
+                out,
+                "{:<24} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                f.name,
+                f.self_count,
+                percent(f.self_count),
+                f.total,
+                percent(f.total)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\n{:<24} {:>20}", "opcode", "count").unwrap();
+        for (opcode, count) in self.opcodes() {
+            let opcode = format!("{opcode:#04x}");
+            writeln!(out, "{opcode:<24} {count:>12} {:>6.2}%", percent(count)).unwrap();
+        }
+
+        writeln!(out, "\n{:<24} {:>20}  function", "pc", "count").unwrap();
+        for (pc, count) in self.pcs().into_iter().take(TOP) {
+            writeln!(
+                out,
+                "{pc:<24} {count:>12} {:>6.2}%  {}",
+                percent(count),
+                self.function_name(self.function_at(pc))
+            )
+            .unwrap();
+        }
+
+        let loops = self.loops();
+        if !loops.is_empty() {
+            writeln!(
+                out,
+                "\n{:<24} {:>20} {:>12}  function",
+                "loop", "instructions", "iterations"
+            )
+            .unwrap();
+            for l in loops.into_iter().take(TOP) {
+                writeln!(
+                    out,
+                    "{:<24} {:>12} {:>6.2}% {:>12}  {}",
+                    format!("{}..={}", l.start, l.end),
+                    l.instructions,
+                    percent(l.instructions),
+                    l.iterations,
+                    self.function_name(self.function_at(l.start))
+                )
+                .unwrap();
+            }
+        }
+
+        out
+    }
+
+    /// The profile in the collapsed stack format read by flame graph tools:
+    /// one line per call stack, its frames separated by `;` starting with
+    /// `node`, followed by the instructions executed with that stack.
+    pub fn collapsed(&self, node: &str) -> String {
+        let mut lines: Vec<String> = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| frame.count > 0)
+            .map(|(id, frame)| {
+                let names: Vec<&str> = std::iter::once(node)
+                    .chain(
+                        self.path(id)
+                            .into_iter()
+                            .skip(1)
+                            .map(|f| self.function_name(self.frames[f].function)),
+                    )
+                    .collect();
+                format!("{} {}", names.join(";"), frame.count)
+            })
+            .collect();
+        lines.sort();
+
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    /// Frames from the root down to `frame`.
+    fn path(&self, mut frame: usize) -> Vec<usize> {
+        let mut path = vec![frame];
+        while frame != 0 {
+            frame = self.frames[frame].parent;
+            path.push(frame);
+        }
+        path.reverse();
+        path
+    }
+
+    fn child(&mut self, parent: usize, function: usize) -> usize {
+        let frames = &mut self.frames;
+        *self.children.entry((parent, function)).or_insert_with(|| {
+            frames.push(Frame {
+                parent,
+                function: Some(function),
+                count: 0,
+            });
+            frames.len() - 1
+        })
+    }
+
+    fn function_at(&self, pc: usize) -> Option<usize> {
+        let i = self
+            .functions
+            .partition_point(|f| f.address <= pc)
+            .checked_sub(1)?;
+        let f = &self.functions[i];
+        (f.size == 0 || pc < f.address + f.size).then_some(i)
+    }
+
+    fn is_call(&self, pc: usize, next: usize, stack: &LinkedList<u32>) -> bool {
+        let Ok(function) = self.functions.binary_search_by_key(&next, |f| f.address) else {
+            return false;
+        };
+        let return_address = stack.iter().rev().nth(self.functions[function].arity);
+        return_address == Some(&(pc as u32 + 5))
+    }
+
+    fn function_name(&self, function: Option<usize>) -> &str {
+        function.map_or("<node>", |f| &self.functions[f].name)
+    }
+}
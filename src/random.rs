@@ -0,0 +1,47 @@
+use crate::snapshot;
+
+/// A node's random number generator, SplitMix64. Each node draws from its
+/// own sequence, derived from the graph's seed and the node's name, so the
+/// numbers a node gets do not depend on how the nodes are scheduled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64, node: &str) -> Self {
+        Self {
+            state: seed ^ snapshot::code_hash(node.as_bytes()),
+        }
+    }
+
+    /// Picks up a sequence where [`state`](Self::state) left it.
+    pub fn from_state(state: u64) -> Self {
+        Self { state }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `bound`, or any `u32` if `bound` is 0.
+    pub fn below(&mut self, bound: u32) -> u32 {
+        match bound {
+            0 => self.next_u64() as u32,
+            _ => (self.next_u64() % bound as u64) as u32,
+        }
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn unit_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u32 << 24) as f32
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    opcode,
+    snapshot::{read_json, write_json},
+    vm::{ExecStatus, NodeMachine},
+};
+
+/// One executed event instruction (see [`opcode::is_event`]), identified by
+/// the node that ran it.
+///
+/// [`opcode::is_event`]: crate::opcode::is_event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub node: usize,
+    /// What the instruction produced, if it is one whose result running it
+    /// again would not reproduce (see [`opcode::has_outcome`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+}
+
+/// The result of an instruction that reaches outside the graph, such as a
+/// host call or a file operation. Replays and the debugger's re-execution
+/// use the recorded outcome in place of running the instruction again.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Outcome {
+    /// Values the instruction pushed, in order.
+    pub values: Vec<u32>,
+    /// Bytes a file read put in memory.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub data: Vec<u8>,
+}
+
+impl Outcome {
+    pub fn value(value: u32) -> Self {
+        Self {
+            values: vec![value],
+            data: vec![],
+        }
+    }
+}
+
+/// Events in the order they ran, shared by every node of a recorded graph.
+/// A node holds the lock while it executes an event, so the order is the
+/// order the events took effect in.
+pub type Journal = Arc<Mutex<Vec<Event>>>;
+
+/// A recorded run, written by `pndm run --record` and read by `pndm replay`.
+///
+/// Between events a node's execution depends only on its own state, so the
+/// order of events across the graph captures every scheduling decision that
+/// could change the outcome, including which message each receive got.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunLog {
+    /// Name and byte code hash of every node, in graph order.
+    pub nodes: Vec<(String, u64)>,
+    pub entry: Vec<String>,
+    pub events: Vec<Event>,
+    /// Every byte of input the run consumed. Nodes read the input only in
+    /// events, so a replay sees the same bytes in the same order.
+    #[serde(default)]
+    pub input: Vec<u8>,
+    /// Seed of the nodes' random number generators.
+    #[serde(default)]
+    pub seed: u64,
+    /// Whether the nodes ran on a virtual clock. Readings of either clock
+    /// are outcomes of their events, but only virtual sleeps take no time.
+    #[serde(default)]
+    pub virtual_clock: bool,
+}
+
+impl RunLog {
+    pub fn read(path: &str) -> Result<Self, Error> {
+        read_json(path)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        write_json(path, self)
+    }
+}
+
+/// Replays `events` on the current thread: each event's node runs up to its
+/// next event and then executes it, with the event's outcome in place of
+/// running it for real. Once the events run out, the start nodes
+/// run until they halt, which they must do without reaching another event,
+/// and the other nodes run until they halt or wait on an event that never
+/// happened.
+pub fn replay(nodes: &mut [NodeMachine], start: &[usize], events: &[Event]) -> Result<(), Error> {
+    for (i, event) in events.iter().enumerate() {
+        let Some(node) = nodes.get_mut(event.node) else {
+            return Err(Error::RecordingMismatch {
+                node: format!("with index {}", event.node),
+            });
+        };
+
+        match node.run_to_event() {
+            ExecStatus::Running => {}
+            ExecStatus::Trapped(e) => return Err(e),
+            _ => return Err(diverged(node, i)),
+        }
+        if opcode::has_outcome(node.opcode()) != event.outcome.is_some() {
+            return Err(diverged(node, i));
+        }
+        if let Some(outcome) = &event.outcome {
+            node.replay_outcome(outcome.clone());
+        }
+        match node.step(1) {
+            ExecStatus::Running | ExecStatus::Halted(_) => {}
+            ExecStatus::Trapped(e) => return Err(e),
+            ExecStatus::Blocked => return Err(diverged(node, i)),
+        }
+    }
+
+    for (n, node) in nodes.iter_mut().enumerate() {
+        match node.run_to_event() {
+            ExecStatus::Halted(_) => {}
+            ExecStatus::Trapped(e) => return Err(e),
+            _ if start.contains(&n) => return Err(diverged(node, events.len())),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn diverged(node: &NodeMachine, event: usize) -> Error {
+    Error::ReplayDiverged {
+        node: node.name().to_string(),
+        event,
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    graph::Limits,
+    vm::{ExecStatus, NodeMachine},
+};
+
+/// Instructions a node runs before the scheduler moves on, unless set with
+/// [`Scheduler::new`].
+pub const DEFAULT_SLICE: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ready,
+    /// Blocked on a send or receive; skipped until it could make progress.
+    Parked,
+    Halted,
+}
+
+/// Where the deterministic scheduler is in its round-robin, so that a run
+/// stopped partway through can continue with exactly the interleaving it
+/// would have had.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Position in the round of the node whose turn it is.
+    position: usize,
+    /// Instructions that node has already run in its current slice.
+    used: u64,
+}
+
+/// When [`Scheduler::run_until`] should stop early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Never,
+    /// Once node `node` has executed `steps` instructions in total.
+    NodeSteps {
+        node: usize,
+        steps: u64,
+    },
+    /// Once the nodes have executed `steps` instructions between them.
+    GraphSteps(u64),
+}
+
+/// Runs every node of a graph, giving each ready node a slice of at most
+/// `slice` instructions per turn.
+///
+/// With one thread, nodes take turns round-robin on the current thread in a
+/// fixed order, the start nodes first and then the rest by index, so a graph
+/// given the same inputs always interleaves the same way. With more, nodes
+/// are taken from a shared run queue by a pool of worker threads and run
+/// truly in parallel; programs without races produce the same results either
+/// way.
+///
+/// Graph-wide limits are checked between slices. The deterministic scheduler
+/// also shortens the last slice so the instruction limit is exact.
+#[derive(Debug, Clone, Copy)]
+pub struct Scheduler {
+    slice: u64,
+    threads: usize,
+    limits: Limits,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLICE)
+    }
+}
+
+impl Scheduler {
+    pub fn new(slice: u64) -> Self {
+        Self {
+            slice: slice.max(1),
+            threads: 1,
+            limits: Limits::default(),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Runs nodes on a pool of `threads` worker threads instead of the
+    /// current thread.
+    pub fn with_threads(self, threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            ..self
+        }
+    }
+
+    /// Applies `limits.instructions` and `limits.time_ms` to the graph as a
+    /// whole.
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// Runs until every node has halted, or until the start nodes have halted
+    /// and every other node is parked waiting for a message that will never
+    /// come. Fails with a deadlock if a start node has not halted and every
+    /// unfinished node is parked.
+    pub fn run(&self, nodes: &mut [NodeMachine], start: &[usize]) -> Result<(), Error> {
+        if self.threads > 1 {
+            return self.run_parallel(nodes, start);
+        }
+
+        let mut cursor = Cursor::default();
+        self.run_until(nodes, start, &mut cursor, Instant::now(), Stop::Never)
+            .map(|_| ())
+    }
+
+    /// Runs on the current thread, whatever the number of threads, from
+    /// `cursor` until the graph finishes as described for [`run`](Self::run)
+    /// or `stop` is reached. Returns whether the graph finished, and leaves
+    /// `cursor` where the next call should pick up. The graph-wide time limit
+    /// counts from `started`, when the run began, so that a run split across
+    /// calls is limited as a whole.
+    pub fn run_until(
+        &self,
+        nodes: &mut [NodeMachine],
+        start: &[usize],
+        cursor: &mut Cursor,
+        started: Instant,
+        stop: Stop,
+    ) -> Result<bool, Error> {
+        let order: Vec<usize> = start
+            .iter()
+            .copied()
+            .chain((0..nodes.len()).filter(|n| !start.contains(n)))
+            .collect();
+        let mut states: Vec<State> = nodes
+            .iter()
+            .map(|n| {
+                if n.is_halted() {
+                    State::Halted
+                } else {
+                    State::Ready
+                }
+            })
+            .collect();
+
+        for node in nodes.iter_mut() {
+            node.set_graph_deadline(self.deadline(started));
+        }
+
+        loop {
+            if cursor.position == 0 && states.iter().all(|&s| s == State::Halted) {
+                return Ok(true);
+            }
+            // A round picked up partway through has already run something.
+            let mut ran = cursor.position > 0;
+
+            while cursor.position < order.len() {
+                let n = order[cursor.position];
+                if states[n] == State::Parked && !nodes[n].is_blocked() {
+                    states[n] = State::Ready;
+                }
+                if states[n] != State::Ready {
+                    cursor.position += 1;
+                    continue;
+                }
+
+                let executed: u64 = nodes.iter().map(|n| n.steps()).sum();
+                self.check_limits(executed, started)?;
+                let mut budget = self.slice - cursor.used;
+                if let Some(limit) = self.limits.instructions {
+                    budget = budget.min(limit - executed);
+                }
+                match stop {
+                    Stop::Never => {}
+                    Stop::NodeSteps { node, steps } if node == n => {
+                        if nodes[n].steps() >= steps {
+                            return Ok(false);
+                        }
+                        budget = budget.min(steps - nodes[n].steps());
+                    }
+                    Stop::NodeSteps { .. } => {}
+                    Stop::GraphSteps(steps) => {
+                        if executed >= steps {
+                            return Ok(false);
+                        }
+                        budget = budget.min(steps - executed);
+                    }
+                }
+
+                ran = true;
+                let before = nodes[n].steps();
+                let status = nodes[n].step(budget);
+                cursor.used += nodes[n].steps() - before;
+
+                // A slice cut short by a limit or a stop keeps the node's
+                // turn, so the next check sees it.
+                if matches!(status, ExecStatus::Running) && cursor.used < self.slice {
+                    continue;
+                }
+                states[n] = match status {
+                    ExecStatus::Running => State::Ready,
+                    ExecStatus::Blocked => State::Parked,
+                    ExecStatus::Halted(_) => State::Halted,
+                    ExecStatus::Trapped(e) => return Err(e),
+                };
+                cursor.position += 1;
+                cursor.used = 0;
+            }
+            cursor.position = 0;
+
+            if !ran {
+                if start.iter().all(|&n| states[n] == State::Halted) {
+                    return Ok(true);
+                }
+                return Err(Error::Deadlock {
+                    nodes: order
+                        .iter()
+                        .filter(|&&n| states[n] == State::Parked)
+                        .map(|&n| nodes[n].name().to_string())
+                        .collect(),
+                });
+            }
+        }
+    }
+
+    /// When the graph-wide time limit runs out for a run that began at
+    /// `started`, along with the limit.
+    fn deadline(&self, started: Instant) -> Option<(Instant, u64)> {
+        let limit_ms = self.limits.time_ms?;
+        Some((started + Duration::from_millis(limit_ms), limit_ms))
+    }
+
+    fn check_limits(&self, executed: u64, started: Instant) -> Result<(), Error> {
+        if let Some(limit) = self.limits.instructions {
+            if executed >= limit {
+                return Err(Error::InstructionLimit { node: None, limit });
+            }
+        }
+        if let Some(limit_ms) = self.limits.time_ms {
+            if started.elapsed() > Duration::from_millis(limit_ms) {
+                return Err(Error::TimeLimit {
+                    node: None,
+                    limit_ms,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_parallel(&self, nodes: &mut [NodeMachine], start: &[usize]) -> Result<(), Error> {
+        let started = Instant::now();
+        for node in nodes.iter_mut() {
+            node.set_graph_deadline(self.deadline(started));
+        }
+        let shared = Mutex::new(Shared {
+            queue: (0..nodes.len())
+                .filter(|&n| !nodes[n].is_halted())
+                .collect(),
+            states: nodes
+                .iter()
+                .map(|n| {
+                    if n.is_halted() {
+                        State::Halted
+                    } else {
+                        State::Ready
+                    }
+                })
+                .collect(),
+            running: 0,
+            executed: nodes.iter().map(|n| n.steps()).sum(),
+            result: None,
+        });
+        let wakeup = Condvar::new();
+        let nodes: Vec<Mutex<&mut NodeMachine>> = nodes.iter_mut().map(Mutex::new).collect();
+
+        if shared.lock().unwrap().queue.is_empty() {
+            return Ok(());
+        }
+
+        let worker = || loop {
+            let n = {
+                let mut shared = shared.lock().unwrap();
+                loop {
+                    if shared.result.is_some() {
+                        return;
+                    }
+                    if let Some(n) = shared.queue.pop_front() {
+                        shared.running += 1;
+                        break n;
+                    }
+                    if shared.running == 0 {
+                        if start.iter().all(|&n| shared.states[n] == State::Halted) {
+                            shared.result = Some(Ok(()));
+                            wakeup.notify_all();
+                            return;
+                        }
+                        let parked = (0..nodes.len())
+                            .filter(|&n| shared.states[n] == State::Parked)
+                            .map(|n| nodes[n].lock().unwrap().name().to_string())
+                            .collect();
+                        shared.result = Some(Err(Error::Deadlock { nodes: parked }));
+                        wakeup.notify_all();
+                        return;
+                    }
+                    shared = wakeup.wait(shared).unwrap();
+                }
+            };
+
+            let (status, executed) = {
+                let mut node = nodes[n].lock().unwrap();
+                let steps = node.steps();
+                (node.step(self.slice), node.steps() - steps)
+            };
+
+            let mut shared = shared.lock().unwrap();
+            shared.running -= 1;
+            shared.executed += executed;
+            if let Err(e) = self.check_limits(shared.executed, started) {
+                shared.result.get_or_insert(Err(e));
+            }
+            match status {
+                ExecStatus::Trapped(e) => {
+                    shared.result.get_or_insert(Err(e));
+                }
+                ExecStatus::Running => shared.queue.push_back(n),
+                ExecStatus::Halted(_) => shared.states[n] = State::Halted,
+                // A message may have arrived between the node blocking and
+                // taking the lock; senders only wake nodes already parked.
+                ExecStatus::Blocked if !nodes[n].lock().unwrap().is_blocked() => {
+                    shared.queue.push_back(n)
+                }
+                ExecStatus::Blocked => shared.states[n] = State::Parked,
+            }
+
+            // The slice may have sent to a parked receiver or made room for a
+            // parked sender.
+            for (p, node) in nodes.iter().enumerate() {
+                if shared.states[p] == State::Parked && !node.lock().unwrap().is_blocked() {
+                    shared.states[p] = State::Ready;
+                    shared.queue.push_back(p);
+                }
+            }
+            wakeup.notify_all();
+        };
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                scope.spawn(worker);
+            }
+        });
+
+        shared.into_inner().unwrap().result.unwrap_or(Ok(()))
+    }
+}
+
+/// Scheduler state shared by the worker threads, guarded by one lock. Node
+/// locks are only ever taken while holding it, or by the worker running that
+/// node's slice, which holds no other lock.
+struct Shared {
+    queue: VecDeque<usize>,
+    states: Vec<State>,
+    /// Number of slices currently executing.
+    running: usize,
+    /// Instructions executed by all nodes.
+    executed: u64,
+    result: Option<Result<(), Error>>,
+}
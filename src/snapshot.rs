@@ -0,0 +1,112 @@
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, message::Message, scheduler::Cursor, vm::ArrayShape};
+
+/// The complete state of one node, enough to resume it exactly where it
+/// stopped. Return addresses live on the operand stack, so `stack` also holds
+/// the node's call frames.
+///
+/// Snapshots are stored as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeSnapshot {
+    pub node: String,
+    /// Hash of the node's byte code, checked on restore so a snapshot is only
+    /// resumed in the code it was taken from.
+    pub code_hash: u64,
+    pub pc: usize,
+    pub stack: Vec<u32>,
+    pub memory: Vec<u8>,
+    /// Arrays declared so far, by base address.
+    pub arrays: Vec<(u32, ArrayShape)>,
+    pub steps: u64,
+    /// Messages waiting in the node's mailbox.
+    pub mailbox: Vec<Message>,
+    /// Bytes of the graph's input consumed when the snapshot was taken.
+    #[serde(default)]
+    pub input: u64,
+    /// The node's clock in milliseconds, set back to it on restore as
+    /// [`Clock::restore`](crate::clock::Clock::restore) describes.
+    #[serde(default)]
+    pub clock: u64,
+    /// Whether the node's clock was virtual.
+    #[serde(default)]
+    pub virtual_clock: bool,
+    /// State of the node's random number generator.
+    #[serde(default)]
+    pub rng: u64,
+}
+
+impl NodeSnapshot {
+    pub fn read(path: &str) -> Result<Self, Error> {
+        read_json(path)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        write_json(path, self)
+    }
+}
+
+/// Every node of a graph at one consistent point, including messages sent
+/// but not yet received, along with where the deterministic scheduler was in
+/// its round.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphCheckpoint {
+    pub nodes: Vec<NodeSnapshot>,
+    pub cursor: Cursor,
+}
+
+impl GraphCheckpoint {
+    pub fn read(path: &str) -> Result<Self, Error> {
+        read_json(path)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        write_json(path, self)
+    }
+}
+
+/// Either kind of snapshot file, as accepted by `pndm resume`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum SnapshotFile {
+    Graph(GraphCheckpoint),
+    Node(NodeSnapshot),
+}
+
+impl SnapshotFile {
+    pub fn read(path: &str) -> Result<Self, Error> {
+        read_json(path)
+    }
+}
+
+pub(crate) fn read_json<T: for<'de> Deserialize<'de>>(path: &str) -> Result<T, Error> {
+    let json = fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_string(),
+        source,
+    })?;
+    serde_json::from_str(&json).map_err(|e| Error::Json {
+        path: path.to_string(),
+        message: e.to_string(),
+    })
+}
+
+pub(crate) fn write_json<T: Serialize>(path: &str, value: &T) -> Result<(), Error> {
+    let json = serde_json::to_string(value).map_err(|e| Error::Json {
+        path: path.to_string(),
+        message: e.to_string(),
+    })?;
+    fs::write(path, json).map_err(|source| Error::Io {
+        path: path.to_string(),
+        source,
+    })
+}
+
+/// 64-bit FNV-1a hash of `code`. Unlike the standard library's hashers it is
+/// stable across Rust versions, so snapshots stay valid after upgrading.
+pub fn code_hash(code: &[u8]) -> u64 {
+    code.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
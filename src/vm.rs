@@ -1,749 +1,2402 @@
 use std::{
-    collections::{HashMap, LinkedList},
+    collections::{BTreeMap, HashMap, LinkedList},
     fs::File,
-    io::Read,
+    io::{self, BufRead, Read},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
+use crate::{
+    clock::Clock,
+    coverage::Coverage,
+    error::{Error, Trap},
+    files::{self, Files},
+    graph::{GraphConfig, Limits},
+    host::HostFn,
+    input::{self, Input},
+    message::{Link, Mailbox, Message},
+    node_file::{
+        DebugInfo, FieldKind, Import, NodeFile, Symbol, SymbolKind, SymbolType, TypeLayout,
+    },
+    opcode,
+    profile::Profile,
+    random::Rng,
+    record::{self, Event, Journal, Outcome, RunLog},
+    scheduler::{Cursor, Scheduler, Stop},
+    snapshot::{self, GraphCheckpoint, NodeSnapshot},
+};
+
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug)]
 pub struct VirtualMachine {
     graph: NodeGraph,
+    config: GraphConfig,
+    scheduler: Scheduler,
+    /// Where a run stopped for a snapshot should continue from.
+    cursor: Cursor,
+    input: Input,
+    /// Seed every node's random number generator was derived from.
+    seed: u64,
 }
 
 impl VirtualMachine {
-    pub fn new(path: &str) -> Self {
-        let mut graph_file = File::open(format!("{path}/graph.json")).expect("could not open file");
-        let mut buffer = String::new();
-        graph_file
-            .read_to_string(&mut buffer)
-            .expect("could not read file");
+    pub fn new(path: &str) -> Result<Self, Error> {
+        Self::with_limits(path, Limits::default(), Limits::default())
+    }
 
-        let graph: HashMap<String, Vec<String>> =
-            serde_json::from_str(&buffer).expect("could not convert from json");
+    /// Loads the graph at `path` like [`new`](Self::new), with the limits
+    /// from [`set_limits`](Self::set_limits) applied before any node is
+    /// loaded, so that `node` can raise the memory limit for a node whose
+    /// file declares more memory than the default.
+    pub fn with_limits(path: &str, graph: Limits, node: Limits) -> Result<Self, Error> {
+        let graph_path = format!("{path}/graph.json");
+        let graph_error = |message: String| Error::Graph {
+            path: graph_path.clone(),
+            message,
+        };
+
+        let buffer = read_file(&graph_path)?;
+        let buffer = String::from_utf8(buffer).map_err(|e| graph_error(e.to_string()))?;
+
+        let mut config = GraphConfig::parse(&buffer).map_err(|e| graph_error(e.to_string()))?;
+        config.limits = graph.or(config.limits);
+        config
+            .check_files(Path::new(path))
+            .map_err(|e| graph_error(e.to_string()))?;
+
+        let ids: HashMap<&str, usize> = config
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(i, node)| (node.as_str(), i))
+            .collect();
+        let mut dirs = vec![];
+        for (i, dir) in config.files.iter().enumerate() {
+            let dir = Path::new(path)
+                .join(dir)
+                .canonicalize()
+                .map_err(|e| graph_error(format!("files[{i}]: {dir}: {e}")))?;
+            dirs.push(dir);
+        }
 
-        let mut ids: HashMap<String, usize> = HashMap::new();
         let mut nodes = vec![];
+        let input = Input::stdin();
+        let clock = Clock::default();
+
+        for (name, node_config) in &config.nodes {
+            let node_path = format!("{path}/{}", config.bytecode_path(name));
+            let limits = node_config.limits.or(node);
+            let mut node = NodeMachine::load(name.clone(), node_path, limits)?;
+            node.id = nodes.len();
+            node.input = input.clone();
+            node.files = Files::new(PathBuf::from(path), dirs.clone());
+            node.clock = clock;
+
+            if let Some(function) = &node_config.entry {
+                let params: Vec<u32> = node_config.params.iter().map(|&p| p as u32).collect();
+                node.prepare_call(function, &params)
+                    .map_err(|e| graph_error(format!("nodes.{name}.entry: {e}")))?;
+            }
 
-        for node in graph.keys() {
-            ids.insert(node.clone(), nodes.len());
-
-            let node = NodeMachine::new(format!("{path}/{node}.k"));
             nodes.push(node);
         }
 
-        let mut adj_list = HashMap::new();
-        for (node, neighbors) in graph {
-            let node_idx = ids[&node];
+        for edge in &config.edges {
+            for (from, to) in edge.links() {
+                let (from, to) = (ids[from], ids[to]);
+
+                let link = Link {
+                    name: edge.name.clone(),
+                    peer: nodes[to].name.clone(),
+                    node: to,
+                    mailbox: nodes[to].mailbox.clone(),
+                    capacity: edge.capacity,
+                };
+                nodes[from].links.push(link);
+            }
+        }
+
+        Ok(Self {
+            graph: NodeGraph::from(nodes),
+            scheduler: Scheduler::default().with_limits(config.limits),
+            cursor: Cursor::default(),
+            config,
+            input,
+            seed: 0,
+        })
+    }
+
+    /// Runs `node` instead of the entry named in `graph.json`.
+    pub fn set_entry_node(&mut self, node: &str) -> Result<(), Error> {
+        self.node_index(node)?;
+        self.config.entry = vec![node.to_string()];
+
+        Ok(())
+    }
+
+    /// Indices of the nodes to start, in order: the entry nodes from
+    /// `graph.json`, or the only node if the graph has just one.
+    pub fn start_nodes(&self) -> Result<Vec<usize>, Error> {
+        match (self.config.entry.as_slice(), self.graph.nodes.as_slice()) {
+            ([], [_]) => Ok(vec![0]),
+            ([], nodes) => Err(Error::AmbiguousEntry {
+                nodes: nodes.iter().map(|n| n.name.clone()).collect(),
+            }),
+            (entry, _) => entry.iter().map(|n| self.node_index(n)).collect(),
+        }
+    }
+
+    /// Sets the number of instructions each node runs before the scheduler
+    /// moves on to the next one.
+    pub fn set_slice(&mut self, slice: u64) {
+        self.scheduler = Scheduler::new(slice)
+            .with_threads(self.scheduler.threads())
+            .with_limits(self.config.limits);
+    }
+
+    /// Runs nodes on `threads` worker threads. One thread, the default, uses
+    /// the deterministic scheduler.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.scheduler = self.scheduler.with_threads(threads);
+    }
+
+    /// Turns array bounds checks on or off for every node.
+    pub fn set_bounds_checks(&mut self, enabled: bool) {
+        for node in &mut self.graph.nodes {
+            node.set_bounds_checks(enabled);
+        }
+    }
+
+    /// Overrides the graph-wide limits from `graph.json` with any limits set
+    /// in `graph`, and gives every node without its own limits those set in
+    /// `node`.
+    pub fn set_limits(&mut self, graph: Limits, node: Limits) -> Result<(), Error> {
+        self.config.limits = graph.or(self.config.limits);
+        self.scheduler = self.scheduler.with_limits(self.config.limits);
+
+        for (machine, config) in self.graph.nodes.iter_mut().zip(self.config.nodes.values()) {
+            machine.set_limits(config.limits.or(node));
+            machine.check_memory()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn node(&self, name: &str) -> Option<&NodeMachine> {
+        self.graph.nodes.iter().find(|n| n.name == name)
+    }
+
+    pub fn nodes(&self) -> &[NodeMachine] {
+        &self.graph.nodes
+    }
+
+    pub(crate) fn nodes_mut(&mut self) -> &mut [NodeMachine] {
+        &mut self.graph.nodes
+    }
+
+    /// Instructions executed by all nodes between them. Under the
+    /// deterministic scheduler this identifies a point in the run.
+    pub fn steps(&self) -> u64 {
+        self.graph.nodes.iter().map(|n| n.steps).sum()
+    }
+
+    /// Runs every node in the graph until it finishes, as described for
+    /// [`Scheduler::run`]. A graph restored from a checkpoint continues where
+    /// it left off.
+    pub fn execute(&mut self) -> Result<(), Error> {
+        self.check_imports()?;
+        let start = self.start_nodes()?;
+        self.begin_output(&start);
+
+        if self.scheduler.threads() > 1 {
+            self.scheduler.run(&mut self.graph.nodes, &start)?;
+        } else {
+            self.run_until(&start, Instant::now(), Stop::Never)?;
+        }
+
+        self.end_output(&start);
+
+        Ok(())
+    }
+
+    /// Runs like [`execute`](Self::execute), but writes a checkpoint of the
+    /// whole graph to `path` once its nodes have executed `steps`
+    /// instructions between them, then carries on. The whole run uses the
+    /// deterministic scheduler.
+    pub fn execute_with_checkpoint(&mut self, steps: u64, path: &str) -> Result<(), Error> {
+        self.check_imports()?;
+        let start = self.start_nodes()?;
+        self.begin_output(&start);
+
+        let started = Instant::now();
+        if self.run_until(&start, started, Stop::GraphSteps(steps))? {
+            eprintln!("the graph finished before {steps} steps; no checkpoint written");
+        } else {
+            self.checkpoint().write(path)?;
+            eprintln!("wrote a checkpoint of the graph to {path}");
+            self.run_until(&start, started, Stop::Never)?;
+        }
+
+        self.end_output(&start);
+
+        Ok(())
+    }
+
+    fn begin_output(&self, start: &[usize]) {
+        for &node in start {
+            self.graph.nodes[node].print_code();
+        }
+        println!("BEGIN PROGRAM OUTPUT -------");
+    }
 
-            let neighbor_idx = neighbors.iter().map(|n| ids[n]).collect();
+    fn end_output(&self, start: &[usize]) {
+        println!("END PROGRAM OUTPUT ----");
+        for &node in start {
+            self.graph.nodes[node].print_state();
+        }
+    }
 
-            adj_list.insert(node_idx, neighbor_idx);
+    /// Runs like [`execute`](Self::execute), but writes a snapshot of the
+    /// start node to `path` once it has executed `steps` instructions, then
+    /// carries on. The whole run uses the deterministic scheduler.
+    pub fn execute_with_snapshot(&mut self, steps: u64, path: &str) -> Result<(), Error> {
+        self.check_imports()?;
+        let start = self.start_nodes()?;
+        let &[node] = start.as_slice() else {
+            return Err(Error::AmbiguousEntry {
+                nodes: start
+                    .iter()
+                    .map(|&n| self.graph.nodes[n].name.clone())
+                    .collect(),
+            });
+        };
+
+        self.begin_output(&start);
+
+        let started = Instant::now();
+        let stop = Stop::NodeSteps { node, steps };
+        let finished = self.run_until(&start, started, stop)?;
+        if finished {
+            eprintln!(
+                "node {} halted before {steps} steps; no snapshot written",
+                self.graph.nodes[node].name
+            );
+        } else {
+            self.graph.nodes[node].snapshot().write(path)?;
+            eprintln!(
+                "wrote a snapshot of node {} to {path}",
+                self.graph.nodes[node].name
+            );
+            self.run_until(&start, started, Stop::Never)?;
         }
 
-        Self {
-            graph: NodeGraph::from(nodes, adj_list),
+        self.end_output(&start);
+
+        Ok(())
+    }
+
+    pub(crate) fn run_until(
+        &mut self,
+        start: &[usize],
+        started: Instant,
+        stop: Stop,
+    ) -> Result<bool, Error> {
+        self.scheduler.run_until(
+            &mut self.graph.nodes,
+            start,
+            &mut self.cursor,
+            started,
+            stop,
+        )
+    }
+
+    /// Makes the input opcodes read from `source` instead of stdin.
+    pub fn set_input(&mut self, source: impl BufRead + Send + 'static) {
+        self.input = Input::new(source);
+        if self.graph.nodes.iter().any(|n| n.journal.is_some()) {
+            self.input.keep_from(0);
+        }
+        for node in &mut self.graph.nodes {
+            node.input = self.input.clone();
         }
     }
 
-    pub fn execute(&mut self) {
-        self.graph.nodes[0].execute();
+    /// Keeps the input consumed from now on, so that restoring a checkpoint
+    /// taken from here can rewind it.
+    pub(crate) fn keep_input(&self) {
+        self.input.keep_from(self.input.position());
+    }
+
+    /// Reseeds every node's random number generator from `seed`. Nodes start
+    /// out seeded with 0.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        for node in &mut self.graph.nodes {
+            node.rng = Rng::new(seed, &node.name);
+        }
+    }
+
+    /// Gives every node a virtual clock starting at 0 in place of the wall
+    /// clock.
+    pub fn use_virtual_clock(&mut self) {
+        for node in &mut self.graph.nodes {
+            node.clock = Clock::Virtual(0);
+        }
+    }
+
+    /// Makes `f` available to every node that imports a host function named
+    /// `name`, replacing any function already registered under that name.
+    /// Fails if a node imports `name` with a different arity.
+    pub fn register_host_fn<F>(&mut self, name: &str, arity: u8, f: F) -> Result<(), Error>
+    where
+        F: Fn(&[u32]) -> Result<u32, String> + Send + Sync + 'static,
+    {
+        let host = HostFn::new(name, arity, f);
+        for node in &self.graph.nodes {
+            if let Some(import) = node.imports.iter().find(|i| node.import_name(i) == name) {
+                if import.arity != arity {
+                    return Err(Error::HostArity {
+                        node: node.name.clone(),
+                        function: name.to_string(),
+                        imported: import.arity,
+                        registered: arity,
+                    });
+                }
+            }
+        }
+        for node in &mut self.graph.nodes {
+            node.link(&host);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a host function has been registered for every import of
+    /// every node. Running the graph checks this before any node starts.
+    pub fn check_imports(&self) -> Result<(), Error> {
+        self.graph
+            .nodes
+            .iter()
+            .try_for_each(NodeMachine::check_imports)
+    }
+
+    /// Starts logging every event the graph executes, for
+    /// [`recording`](Self::recording).
+    pub fn record(&mut self) {
+        self.keep_input();
+        let journal = Journal::default();
+        for node in &mut self.graph.nodes {
+            node.journal = Some(journal.clone());
+        }
+    }
+
+    /// The run so far, if [`record`](Self::record) was called.
+    pub fn recording(&self) -> Option<RunLog> {
+        let journal = self.graph.nodes.first()?.journal.as_ref()?;
+
+        Some(RunLog {
+            nodes: self
+                .graph
+                .nodes
+                .iter()
+                .map(|n| (n.name.clone(), snapshot::code_hash(&n.byte_code)))
+                .collect(),
+            entry: self
+                .start_nodes()
+                .ok()?
+                .iter()
+                .map(|&n| self.graph.nodes[n].name.clone())
+                .collect(),
+            events: journal.lock().unwrap().clone(),
+            input: self.input.consumed(),
+            seed: self.seed,
+            virtual_clock: self.graph.nodes[0].clock.is_virtual(),
+        })
+    }
+
+    /// Starts counting the instructions every node executes, for
+    /// [`profiles`](Self::profiles).
+    pub fn profile(&mut self) {
+        for node in &mut self.graph.nodes {
+            node.start_profile();
+        }
+    }
+
+    /// Each node's name and profile, if [`profile`](Self::profile) was
+    /// called.
+    pub fn profiles(&self) -> Vec<(&str, &Profile)> {
+        self.graph
+            .nodes
+            .iter()
+            .filter_map(|n| Some((n.name(), n.profile.as_ref()?)))
+            .collect()
+    }
+
+    /// Starts recording which instructions and branches every node executes,
+    /// for [`coverage`](Self::coverage).
+    pub fn collect_coverage(&mut self) {
+        for node in &mut self.graph.nodes {
+            node.start_coverage();
+        }
+    }
+
+    /// Each node's name and coverage, if
+    /// [`collect_coverage`](Self::collect_coverage) was called.
+    pub fn coverage(&self) -> Vec<(&str, &Coverage)> {
+        self.graph
+            .nodes
+            .iter()
+            .filter_map(|n| Some((n.name(), n.coverage.as_ref()?)))
+            .collect()
+    }
+
+    /// Reruns a recorded run on the current thread, executing events in the
+    /// order they were recorded in. Host functions need not be registered,
+    /// as their results come from the log.
+    pub fn replay(&mut self, log: &RunLog) -> Result<(), Error> {
+        let nodes: Vec<(String, u64)> = self
+            .graph
+            .nodes
+            .iter()
+            .map(|n| (n.name.clone(), snapshot::code_hash(&n.byte_code)))
+            .collect();
+        if nodes != log.nodes {
+            let node = (0..nodes.len().max(log.nodes.len()))
+                .find(|&i| nodes.get(i) != log.nodes.get(i))
+                .and_then(|i| nodes.get(i).or(log.nodes.get(i)))
+                .map_or(String::new(), |(name, _)| name.clone());
+            return Err(Error::RecordingMismatch { node });
+        }
+
+        self.set_input(io::Cursor::new(log.input.clone()));
+        self.set_seed(log.seed);
+        let clock = if log.virtual_clock {
+            Clock::Virtual(0)
+        } else {
+            Clock::default()
+        };
+        for node in &mut self.graph.nodes {
+            node.clock = clock;
+        }
+        self.config.entry = log.entry.clone();
+        let start = self.start_nodes()?;
+        self.begin_output(&start);
+        record::replay(&mut self.graph.nodes, &start, &log.events)?;
+        self.end_output(&start);
+
+        Ok(())
+    }
+
+    /// Captures every node, the messages waiting in their mailboxes and the
+    /// scheduler's position. Nodes only stop between instructions and a send
+    /// delivers its message within one instruction, so this is always a
+    /// consistent cut of the graph.
+    pub fn checkpoint(&self) -> GraphCheckpoint {
+        GraphCheckpoint {
+            nodes: self.graph.nodes.iter().map(NodeMachine::snapshot).collect(),
+            cursor: self.cursor,
+        }
+    }
+
+    /// Restores every node from `checkpoint`, which must cover exactly the
+    /// nodes of this graph. Running the graph with the deterministic
+    /// scheduler then behaves as the original run did from that point.
+    pub fn restore(&mut self, checkpoint: GraphCheckpoint) -> Result<(), Error> {
+        for node in &self.graph.nodes {
+            if !checkpoint.nodes.iter().any(|s| s.node == node.name) {
+                return Err(Error::MissingSnapshot {
+                    node: node.name.clone(),
+                });
+            }
+        }
+        for snapshot in checkpoint.nodes {
+            self.restore_node(snapshot)?;
+        }
+        self.cursor = checkpoint.cursor;
+
+        Ok(())
+    }
+
+    /// Restores the node a snapshot was taken of. Other nodes are left as
+    /// they are.
+    pub fn restore_node(&mut self, snapshot: NodeSnapshot) -> Result<(), Error> {
+        let node = self.node_index(&snapshot.node)?;
+        self.graph.nodes[node].restore(snapshot)
+    }
+
+    pub(crate) fn node_index(&self, node: &str) -> Result<usize, Error> {
+        self.graph
+            .nodes
+            .iter()
+            .position(|n| n.name == node)
+            .ok_or_else(|| Error::UnknownNode {
+                node: node.to_string(),
+            })
+    }
+
+    /// Calls the function `function` exported by `node` with `args` and
+    /// returns its return value, or `None` if it returns nothing. No other
+    /// node runs, but the graph-wide limits still apply to the call.
+    pub fn call(&mut self, node: &str, function: &str, args: &[u32]) -> Result<Option<u32>, Error> {
+        let index = self.node_index(node)?;
+        let node = &mut self.graph.nodes[index];
+        node.check_imports()?;
+        node.prepare_call(function, args)?;
+        self.scheduler.run_until(
+            std::slice::from_mut(node),
+            &[0],
+            &mut Cursor::default(),
+            Instant::now(),
+            Stop::Never,
+        )?;
+
+        Ok(node.stack.pop_back())
     }
 }
 
 #[derive(Debug)]
 pub struct NodeGraph {
     nodes: Vec<NodeMachine>,
-    adj_list: HashMap<usize, Vec<usize>>,
 }
 
 impl NodeGraph {
-    pub fn from(nodes: Vec<NodeMachine>, adj_list: HashMap<usize, Vec<usize>>) -> Self {
-        Self { nodes, adj_list }
+    /// Edges live in each node's links, which sends are checked against.
+    pub fn from(nodes: Vec<NodeMachine>) -> Self {
+        Self { nodes }
     }
 }
 
+/// Where [`NodeMachine::step`] stopped.
+#[derive(Debug)]
+pub enum ExecStatus {
+    /// Used up its instruction budget and can continue.
+    Running,
+    /// Waiting on a receive with an empty mailbox, or a send to a full link.
+    Blocked,
+    /// Ran off the end of its code, or returned from its outermost function.
+    /// Holds the value left on top of the stack, if any.
+    Halted(Option<u32>),
+    /// Stopped by an [`Error::Trap`] or by exceeding one of its limits.
+    Trapped(Error),
+}
+
+enum Flow {
+    Next,
+    Blocked,
+}
+
 #[derive(Debug)]
 pub struct NodeMachine {
+    id: usize,
+    name: String,
     byte_code: Vec<u8>,
+    strings: Vec<String>,
+    symbols: Vec<Symbol>,
+    debug: Option<DebugInfo>,
+    types: Vec<TypeLayout>,
+    symbol_types: Vec<SymbolType>,
+    imports: Vec<Import>,
+    /// The registered function for each import, by index.
+    host_fns: Vec<Option<HostFn>>,
     pc: usize,
     stack: LinkedList<u32>,
     memory: Vec<u8>,
+    mailbox: Mailbox,
+    links: Vec<Link>,
+    input: Input,
+    files: Files,
+    clock: Clock,
+    rng: Rng,
+    steps: u64,
+    limits: Limits,
+    /// Time spent executing instructions, which excludes time spent waiting
+    /// for other nodes.
+    busy: Duration,
+    /// When the slice being executed started.
+    slice_started: Instant,
+    /// When the graph-wide time limit runs out, along with that limit in
+    /// milliseconds. Set by the scheduler so that sleeps end there.
+    graph_deadline: Option<(Instant, u64)>,
+    /// Shape of each array declared with `0x80` or `0x81`, by base address.
+    arrays: HashMap<u32, ArrayShape>,
+    bounds_checks: bool,
+    /// Where executed events are logged while the graph is being recorded.
+    journal: Option<Journal>,
+    /// Makes `step` stop before the next event, for replays.
+    pause_at_events: bool,
+    /// Suppresses output, while the debugger re-executes instructions whose
+    /// output was already printed.
+    muted: bool,
+    /// Memory written since the last [`take_stores`](Self::take_stores),
+    /// while the debugger is tracking stores for watchpoints.
+    stores: Option<Vec<Range<usize>>>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    /// Outcomes of the instructions that reach outside the graph, by the
+    /// step they ran at, kept while they may run again in a replay or after
+    /// the debugger steps back.
+    outcomes: Option<BTreeMap<u64, Outcome>>,
+    /// Outcome of the instruction just executed, for the journal.
+    outcome: Option<Outcome>,
+    /// Whether the last conditional branch jumped, for coverage: a branch to
+    /// the next instruction lands in the same place either way.
+    branch_taken: bool,
 }
 
+/// Element size and dimensions of a declared array, outermost dimension
+/// first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArrayShape {
+    pub elem_size: u32,
+    pub dims: Vec<u32>,
+}
+
+impl ArrayShape {
+    /// Total number of elements.
+    fn len(&self) -> u32 {
+        self.dims.iter().product()
+    }
+}
+
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// Memory limit, in bytes, for nodes that do not set `limits.memory`.
+pub const DEFAULT_MEMORY_LIMIT: u32 = 64 << 20;
+
 impl NodeMachine {
-    pub fn new(path: String) -> Self {
-        let mut file = File::open(path).expect("could not open file");
+    /// Loads the node file at `path`, naming the node after the file.
+    pub fn new(path: String) -> Result<Self, Error> {
+        let name = Path::new(&path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Self::load(name, path, Limits::default())
+    }
 
-        let mut byte_code = vec![];
-        file.read_to_end(&mut byte_code)
-            .expect("could not read file");
+    /// Loads the node file at `path` with `limits`.
+    pub fn load(name: String, path: String, limits: Limits) -> Result<Self, Error> {
+        let bytes = read_file(&path)?;
+        let file = NodeFile::parse(&bytes).map_err(|source| Error::Load { path, source })?;
 
-        Self {
-            byte_code,
-            pc: 0,
+        Self::from_node_file(name, file, limits)
+    }
+
+    /// Builds a machine ready to run `file` from its entry point with
+    /// `limits`. `memory` is sized and initialized from the data section up
+    /// front, so the declare opcodes only need to grow it for legacy files;
+    /// the size the file declares is checked against the memory limit before
+    /// it is allocated.
+    pub fn from_node_file(name: String, file: NodeFile, limits: Limits) -> Result<Self, Error> {
+        check_memory_size(&name, file.memory_size, limits)?;
+
+        let mut memory = file.data;
+        memory.resize(file.memory_size as usize, 0);
+        let rng = Rng::new(0, &name);
+
+        Ok(Self {
+            id: 0,
+            name,
+            byte_code: file.code,
+            strings: file.strings,
+            symbols: file.symbols,
+            debug: file.debug,
+            types: file.types,
+            symbol_types: file.symbol_types,
+            host_fns: vec![None; file.imports.len()],
+            imports: file.imports,
+            pc: file.entry as usize,
             stack: LinkedList::new(),
-            memory: vec![],
+            memory,
+            mailbox: Mailbox::default(),
+            links: vec![],
+            input: Input::stdin(),
+            files: Files::default(),
+            clock: Clock::default(),
+            rng,
+            steps: 0,
+            limits,
+            busy: Duration::ZERO,
+            slice_started: Instant::now(),
+            graph_deadline: None,
+            arrays: HashMap::new(),
+            bounds_checks: true,
+            journal: None,
+            pause_at_events: false,
+            muted: false,
+            stores: None,
+            profile: None,
+            coverage: None,
+            outcomes: None,
+            outcome: None,
+            branch_taken: false,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Offset of the next instruction to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Queue of messages waiting for this node.
+    pub fn mailbox(&self) -> &Mailbox {
+        &self.mailbox
+    }
+
+    pub fn stack(&self) -> &LinkedList<u32> {
+        &self.stack
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Limits this node to `limits.instructions` instructions,
+    /// `limits.time_ms` milliseconds of execution time and `limits.memory`
+    /// bytes of memory.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Turns checking array indices against declared lengths on or off. Checks
+    /// are on by default; turning them off is meant for trusted release runs.
+    pub fn set_bounds_checks(&mut self, enabled: bool) {
+        self.bounds_checks = enabled;
+    }
+
+    /// Captures the node's state. Its limits and bounds check setting are
+    /// configuration, not state, and are not included.
+    pub fn snapshot(&self) -> NodeSnapshot {
+        let mut arrays: Vec<(u32, ArrayShape)> = self
+            .arrays
+            .iter()
+            .map(|(&base, shape)| (base, shape.clone()))
+            .collect();
+        arrays.sort_by_key(|&(base, _)| base);
+
+        NodeSnapshot {
+            node: self.name.clone(),
+            code_hash: snapshot::code_hash(&self.byte_code),
+            pc: self.pc,
+            stack: self.stack.iter().copied().collect(),
+            memory: self.memory.clone(),
+            arrays,
+            steps: self.steps,
+            mailbox: self.mailbox.lock().unwrap().iter().copied().collect(),
+            input: self.input.position(),
+            clock: self.clock.ticks(),
+            virtual_clock: self.clock.is_virtual(),
+            rng: self.rng.state(),
+        }
+    }
+
+    /// Puts the node back in the state `snapshot` captured, which must have
+    /// been taken from the same byte code.
+    pub fn restore(&mut self, snapshot: NodeSnapshot) -> Result<(), Error> {
+        if snapshot.code_hash != snapshot::code_hash(&self.byte_code) {
+            return Err(Error::SnapshotMismatch {
+                node: snapshot.node,
+            });
         }
+
+        self.pc = snapshot.pc;
+        self.stack = snapshot.stack.into_iter().collect();
+        self.memory = snapshot.memory;
+        self.arrays = snapshot.arrays.into_iter().collect();
+        self.steps = snapshot.steps;
+        self.busy = Duration::ZERO;
+        *self.mailbox.lock().unwrap() = snapshot.mailbox.into_iter().collect();
+        self.input.seek(snapshot.input);
+        self.clock.restore(snapshot.clock, snapshot.virtual_clock);
+        self.rng = Rng::from_state(snapshot.rng);
+
+        self.check_memory()
+    }
+
+    /// Checks that the memory the node file declares fits within the node's
+    /// memory limit.
+    pub fn check_memory(&self) -> Result<(), Error> {
+        check_memory_size(&self.name, self.memory.len() as u32, self.limits)
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn execute(&mut self) -> Result<(), Error> {
+        self.check_imports()?;
+        self.print_code();
+        println!("BEGIN PROGRAM OUTPUT -------");
+        self.run_to_end()?;
+        println!("END PROGRAM OUTPUT ----");
+        self.print_state();
+
+        Ok(())
     }
 
-    pub fn execute(&mut self) {
+    fn print_code(&self) {
         println!("{:?}", self.byte_code);
         println!("{} bytes", self.byte_code.len());
-        println!("BEGIN PROGRAM OUTPUT -------");
-        while self.pc < self.byte_code.len() {
-            let opcode = self.byte_code[self.pc];
-            // println!("{opcode}");
-            match opcode {
-                0x10 => {
-                    let data: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+    }
 
-                    self.stack.push_back(data);
+    /// Prints the stack, memory and every typed data symbol.
+    pub fn print_state(&self) {
+        println!("{:?}", self.stack);
+        println!("{:?}", self.memory);
+        for ty in &self.symbol_types {
+            let symbol = &self.symbols[ty.symbol as usize];
+            println!(
+                "{} = {}",
+                self.strings[symbol.name as usize],
+                self.format_struct(ty.layout, symbol.address)
+            );
+        }
+    }
 
-                    self.pc += 4;
+    /// Formats the struct with layout `layout` stored at `addr` as
+    /// `Name { field: value, .. }`, or `?` for fields outside memory.
+    pub fn format_struct(&self, layout: u32, addr: u32) -> String {
+        let layout = &self.types[layout as usize];
+        let fields: Vec<String> = layout
+            .fields
+            .iter()
+            .map(|field| {
+                let addr = addr.checked_add(field.offset);
+                let value = match (field.kind, addr) {
+                    (FieldKind::Struct(nested), Some(addr)) => self.format_struct(nested, addr),
+                    (kind, Some(addr)) => self.format_field(kind, addr),
+                    (_, None) => "?".to_string(),
+                };
+                format!("{}: {value}", self.strings[field.name as usize])
+            })
+            .collect();
+
+        format!(
+            "{} {{ {} }}",
+            self.strings[layout.name as usize],
+            fields.join(", ")
+        )
+    }
+
+    fn format_field(&self, kind: FieldKind, addr: u32) -> String {
+        let value = match kind {
+            FieldKind::Int => self.load_u32(addr).map(|v| (v as i32).to_string()),
+            FieldKind::Float => self.load_u32(addr).map(|v| f32::from_bits(v).to_string()),
+            FieldKind::Bool => self.load_u8(addr).map(|v| (v != 0).to_string()),
+            FieldKind::Char => self.load_u8(addr).map(|v| format!("{:?}", v as char)),
+            FieldKind::Struct(_) => unreachable!("nested structs are formatted by format_struct"),
+        };
+        value.unwrap_or_else(|_| "?".to_string())
+    }
+
+    /// Runs until the machine halts. Nothing else runs while this node is
+    /// blocked, so blocking is reported as a deadlock.
+    fn run_to_end(&mut self) -> Result<(), Error> {
+        loop {
+            match self.step(u64::MAX) {
+                ExecStatus::Running => {}
+                ExecStatus::Halted(_) => return Ok(()),
+                ExecStatus::Blocked => {
+                    return Err(Error::Deadlock {
+                        nodes: vec![self.name.clone()],
+                    })
                 }
-                0x11 => {
-                    let data = u32::from_be_bytes([
-                        self.byte_code[self.pc + 1],
-                        self.byte_code[self.pc + 2],
-                        self.byte_code[self.pc + 3],
-                        self.byte_code[self.pc + 4],
-                    ]);
+                ExecStatus::Trapped(e) => return Err(e),
+            }
+        }
+    }
 
-                    self.stack.push_back(data);
+    pub fn is_halted(&self) -> bool {
+        self.pc >= self.byte_code.len()
+    }
 
-                    self.pc += 4;
+    /// Whether the next instruction would block: a receive with an empty
+    /// mailbox, or a send along a full link.
+    pub fn is_blocked(&self) -> bool {
+        match self.byte_code.get(self.pc) {
+            Some(0xA0) => {
+                let target = &self.strings[opcode::operand_u32(&self.byte_code, self.pc) as usize];
+                self.links
+                    .iter()
+                    .find(|l| l.is_named(target))
+                    .is_some_and(|l| l.is_full(&l.mailbox.lock().unwrap(), self.id))
+            }
+            Some(0xA1) => self.mailbox.lock().unwrap().is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Calls the exported function `function` with `args` and runs it to
+    /// completion. Arguments are pushed in order, exactly as a caller inside
+    /// the program would push them after its return address; with no return
+    /// address below them, the function's final return halts the machine and
+    /// leaves its result, if any, on the stack.
+    pub fn call(&mut self, function: &str, args: &[u32]) -> Result<Option<u32>, Error> {
+        self.check_imports()?;
+        self.prepare_call(function, args)?;
+        self.run_to_end()?;
+
+        Ok(self.stack.pop_back())
+    }
+
+    /// Points the machine at the start of the exported function `function`
+    /// with `args` on the stack, without running it.
+    pub fn prepare_call(&mut self, function: &str, args: &[u32]) -> Result<(), Error> {
+        let symbol = self
+            .symbols
+            .iter()
+            .find(|s| {
+                s.exported
+                    && s.kind == SymbolKind::Function
+                    && self.strings[s.name as usize] == function
+            })
+            .ok_or_else(|| Error::UnknownExport {
+                node: self.name.clone(),
+                function: function.to_string(),
+            })?;
+
+        if symbol.arity as usize != args.len() {
+            return Err(Error::WrongArgumentCount {
+                node: self.name.clone(),
+                function: function.to_string(),
+                expected: symbol.arity,
+                found: args.len(),
+            });
+        }
+
+        self.pc = symbol.address as usize;
+        self.stack = args.iter().copied().collect();
+
+        Ok(())
+    }
+
+    /// Fails with the first import that no host function has been registered
+    /// for.
+    pub fn check_imports(&self) -> Result<(), Error> {
+        match self.host_fns.iter().position(Option::is_none) {
+            Some(index) => Err(Error::MissingImport {
+                node: self.name.clone(),
+                function: self.import_name(&self.imports[index]).to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves every import named after `host` to it.
+    fn link(&mut self, host: &HostFn) {
+        for (index, import) in self.imports.iter().enumerate() {
+            if self.strings[import.name as usize] == host.name() {
+                self.host_fns[index] = Some(host.clone());
+            }
+        }
+    }
+
+    fn import_name(&self, import: &Import) -> &str {
+        &self.strings[import.name as usize]
+    }
+
+    /// Sets when the graph-wide time limit runs out, and that limit in
+    /// milliseconds, if there is one.
+    pub(crate) fn set_graph_deadline(&mut self, deadline: Option<(Instant, u64)>) {
+        self.graph_deadline = deadline;
+    }
+
+    /// The earlier of when the node's and the graph's time limits run out,
+    /// with the trap that reaching it raises.
+    fn sleep_deadline(&self) -> Option<(Instant, Trap)> {
+        let node = self.limits.time_ms.map(|limit_ms| {
+            let left = Duration::from_millis(limit_ms)
+                .saturating_sub(self.busy + self.slice_started.elapsed());
+            let trap = Trap::TimeLimit {
+                graph: false,
+                limit_ms,
+            };
+            (Instant::now() + left, trap)
+        });
+        let graph = self.graph_deadline.map(|(at, limit_ms)| {
+            let trap = Trap::TimeLimit {
+                graph: true,
+                limit_ms,
+            };
+            (at, trap)
+        });
+
+        node.into_iter().chain(graph).min_by_key(|&(at, _)| at)
+    }
+
+    /// Runs at most `budget` instructions and reports where the machine
+    /// stopped. A blocked or trapped machine has not executed the instruction
+    /// at its pc, so stepping it again retries that instruction.
+    pub fn step(&mut self, budget: u64) -> ExecStatus {
+        let started = Instant::now();
+        self.slice_started = started;
+        let status = self.step_timed(budget, started);
+        self.busy += started.elapsed();
+
+        status
+    }
+
+    fn step_timed(&mut self, budget: u64, started: Instant) -> ExecStatus {
+        let mut executed = 0;
+        while self.pc < self.byte_code.len() {
+            if executed == budget {
+                return ExecStatus::Running;
+            }
+
+            if self.pause_at_events && opcode::is_event(self.byte_code[self.pc]) {
+                return ExecStatus::Running;
+            }
+
+            if let Some(limit) = self.limits.instructions {
+                if self.steps >= limit {
+                    return ExecStatus::Trapped(Error::InstructionLimit {
+                        node: Some(self.name.clone()),
+                        limit,
+                    });
                 }
-                0x12 => {
-                    self.stack.pop_back();
+            }
+            // Reading the clock is slow next to an instruction, so only check
+            // the time limit every so often.
+            if let Some(limit_ms) = self.limits.time_ms {
+                if executed % TIME_CHECK_INTERVAL == 0
+                    && self.busy + started.elapsed() > Duration::from_millis(limit_ms)
+                {
+                    return ExecStatus::Trapped(Error::TimeLimit {
+                        node: Some(self.name.clone()),
+                        limit_ms,
+                    });
                 }
-                0x13 => {
-                    let offset: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
-
-                    self.stack.push_back(self.pc as u32 + offset);
+            }
 
-                    self.pc += 4;
+            let pc = self.pc;
+            match self.execute_logged() {
+                Ok(Flow::Next) => {
+                    executed += 1;
+                    if let Some(profile) = &mut self.profile {
+                        profile.record(pc, self.byte_code[pc], self.pc, &self.stack);
+                    }
+                    if let Some(coverage) = &mut self.coverage {
+                        coverage.record(pc, self.byte_code[pc], self.branch_taken);
+                    }
                 }
-                0x14 => {
-                    let data = self.byte_code[self.pc + 1] as u32;
+                Ok(Flow::Blocked) => return ExecStatus::Blocked,
+                Err(Trap::MemoryLimit { requested, limit }) => {
+                    return ExecStatus::Trapped(Error::MemoryLimit {
+                        node: self.name.clone(),
+                        requested,
+                        limit,
+                    })
+                }
+                Err(Trap::TimeLimit { graph, limit_ms }) => {
+                    return ExecStatus::Trapped(Error::TimeLimit {
+                        node: (!graph).then(|| self.name.clone()),
+                        limit_ms,
+                    })
+                }
+                Err(trap) => {
+                    return ExecStatus::Trapped(Error::Trap {
+                        node: self.name.clone(),
+                        pc: self.pc,
+                        trap,
+                    })
+                }
+            }
+        }
 
-                    self.stack.push_back(data);
+        ExecStatus::Halted(self.stack.back().copied())
+    }
 
-                    self.pc += 1;
-                }
-                0x15 => {
-                    let data = self.byte_code[self.pc + 1] as u32;
+    /// Starts counting executed instructions by opcode, offset and function.
+    pub fn start_profile(&mut self) {
+        let functions = self
+            .symbols
+            .iter()
+            .filter(|s| s.kind == SymbolKind::Function)
+            .map(|s| {
+                let name = self.strings[s.name as usize].clone();
+                (name, s.address as usize, s.size as usize, s.arity as usize)
+            })
+            .collect();
+        self.profile = Some(Profile::new(self.byte_code.len(), functions));
+    }
 
-                    self.stack.push_back(data);
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
 
-                    self.pc += 1;
-                }
-                0x20 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+    /// Starts recording which instructions run and which way each
+    /// conditional branch goes.
+    pub fn start_coverage(&mut self) {
+        let source = self.debug.as_ref().map(|debug| {
+            let file = self.strings[debug.file as usize].clone();
+            (file, debug.lines.clone())
+        });
+        self.coverage = Some(Coverage::new(&self.byte_code, source));
+    }
 
-                    if addr + 4 > self.memory.len() as u32 {
-                        let addition = addr + 4 - self.memory.len() as u32;
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
 
-                        for _ in 0..addition {
-                            self.memory.push(0);
-                        }
-                    }
+    pub(crate) fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
 
-                    self.pc += 4;
-                }
-                0x21 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+    /// Starts keeping the memory ranges written by each instruction, for
+    /// [`take_stores`](Self::take_stores).
+    pub(crate) fn track_stores(&mut self) {
+        self.stores = Some(vec![]);
+    }
 
-                    if addr + 4 > self.memory.len() as u32 {
-                        let addition = addr + 4 - self.memory.len() as u32;
+    /// Memory ranges written since the last call, in order.
+    pub(crate) fn take_stores(&mut self) -> Vec<Range<usize>> {
+        self.stores.as_mut().map(std::mem::take).unwrap_or_default()
+    }
 
-                        for _ in 0..addition {
-                            self.memory.push(0);
-                        }
-                    }
+    /// Starts keeping the outcome of every instruction that reaches outside
+    /// the graph, so that running it again gives the same result.
+    pub(crate) fn track_outcomes(&mut self) {
+        self.outcomes.get_or_insert_default();
+    }
 
-                    self.pc += 4;
-                }
-                0x22 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+    /// Makes the next instruction, which must be one that
+    /// [has an outcome](opcode::has_outcome), produce `outcome` instead of
+    /// running for real.
+    pub(crate) fn replay_outcome(&mut self, outcome: Outcome) {
+        self.outcomes
+            .get_or_insert_default()
+            .insert(self.steps, outcome);
+    }
 
-                    let data = (self.memory[addr as usize] as u32) << 24
-                        | (self.memory[addr as usize + 1] as u32) << 16
-                        | (self.memory[addr as usize + 2] as u32) << 8
-                        | (self.memory[addr as usize + 3] as u32);
+    /// Opcode of the next instruction, or 0 past the end of the code.
+    pub(crate) fn opcode(&self) -> u8 {
+        self.byte_code.get(self.pc).copied().unwrap_or(0)
+    }
 
-                    self.stack.push_back(data);
+    /// Runs until the next event instruction without executing it, returning
+    /// `Running` once there.
+    pub(crate) fn run_to_event(&mut self) -> ExecStatus {
+        self.pause_at_events = true;
+        let status = self.step(u64::MAX);
+        self.pause_at_events = false;
 
-                    self.pc += 4;
-                }
-                0x23 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+        status
+    }
 
-                    let data = (self.memory[addr as usize] as u32) << 24
-                        | (self.memory[addr as usize + 1] as u32) << 16
-                        | (self.memory[addr as usize + 2] as u32) << 8
-                        | (self.memory[addr as usize + 3] as u32);
+    /// Executes the next instruction, adding it to the journal if the graph
+    /// is being recorded and it is an event.
+    fn execute_logged(&mut self) -> Result<Flow, Trap> {
+        let journal = match &self.journal {
+            Some(journal) if opcode::is_event(self.byte_code[self.pc]) => journal.clone(),
+            _ => return self.execute_instruction(),
+        };
+
+        let mut events = journal.lock().unwrap();
+        let flow = self.execute_instruction()?;
+        if let Flow::Next = flow {
+            events.push(Event {
+                node: self.id,
+                outcome: self.outcome.take(),
+            });
+        }
 
-                    self.stack.push_back(data);
+        Ok(flow)
+    }
 
-                    self.pc += 4;
-                }
-                0x24 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+    fn execute_instruction(&mut self) -> Result<Flow, Trap> {
+        let opcode = self.byte_code[self.pc];
 
-                    let data = self.stack.pop_back().unwrap();
+        // The code was decoded when it was loaded, but a jump can still land
+        // in the middle of an instruction.
+        let operands = opcode::operand_len(opcode).ok_or(Trap::InvalidOpcode { opcode })?;
+        if self.pc + operands >= self.byte_code.len() {
+            return Err(Trap::TruncatedInstruction);
+        }
+        match opcode {
+            0x10 => {
+                let data: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    self.memory[addr as usize] = ((data & 0xFF000000) >> 24) as u8;
-                    self.memory[addr as usize + 1] = ((data & 0x00FF0000) >> 16) as u8;
-                    self.memory[addr as usize + 2] = ((data & 0x0000FF00) >> 8) as u8;
-                    self.memory[addr as usize + 3] = (data & 0x000000FF) as u8;
+                self.stack.push_back(data);
 
-                    self.pc += 4;
-                }
-                0x25 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x11 => {
+                let data = u32::from_be_bytes([
+                    self.byte_code[self.pc + 1],
+                    self.byte_code[self.pc + 2],
+                    self.byte_code[self.pc + 3],
+                    self.byte_code[self.pc + 4],
+                ]);
 
-                    let data = self.stack.pop_back().unwrap();
+                self.stack.push_back(data);
 
-                    self.memory[addr as usize] = ((data & 0xFF000000) >> 24) as u8;
-                    self.memory[addr as usize + 1] = ((data & 0x00FF0000) >> 16) as u8;
-                    self.memory[addr as usize + 2] = ((data & 0x0000FF00) >> 8) as u8;
-                    self.memory[addr as usize + 3] = (data & 0x000000FF) as u8;
+                self.pc += 4;
+            }
+            0x12 => {
+                self.pop()?;
+            }
+            0x13 => {
+                let offset: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let addr = (self.pc as u32)
+                    .checked_add(offset)
+                    .ok_or(Trap::AddressOverflow)?;
+                self.stack.push_back(addr);
+
+                self.pc += 4;
+            }
+            0x14 => {
+                let data = self.byte_code[self.pc + 1] as u32;
 
-                    self.pc += 4;
-                }
-                0x26 => {}
-                0x27 => {}
-                0x28 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
-
-                    if addr + 1 > self.memory.len() as u32 {
-                        let addition = addr + 1 - self.memory.len() as u32;
-
-                        for _ in 0..addition {
-                            self.memory.push(0);
-                        }
-                    }
+                self.stack.push_back(data);
 
-                    self.pc += 4;
-                }
-                0x29 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 1;
+            }
+            0x15 => {
+                let data = self.byte_code[self.pc + 1] as u32;
 
-                    let data = self.memory[addr as usize] as u32;
+                self.stack.push_back(data);
 
-                    self.stack.push_back(data);
+                self.pc += 1;
+            }
+            0x20 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    self.pc += 4;
-                }
-                0x2A => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.reserve(addr, 4)?;
 
-                    let data = self.stack.pop_back().unwrap();
+                self.pc += 4;
+            }
+            0x21 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    self.memory[addr as usize] = (data & 0xFF) as u8;
+                self.reserve(addr, 4)?;
 
-                    self.pc += 4;
-                }
-                0x2C => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x22 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    if addr + 1 > self.memory.len() as u32 {
-                        let addition = addr + 1 - self.memory.len() as u32;
+                let data = self.load_u32(addr)?;
 
-                        for _ in 0..addition {
-                            self.memory.push(0);
-                        }
-                    }
+                self.stack.push_back(data);
 
-                    self.pc += 4;
-                }
-                0x2D => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x23 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let data = self.memory[addr as usize] as u32;
+                let data = self.load_u32(addr)?;
 
-                    self.stack.push_back(data);
+                self.stack.push_back(data);
 
-                    self.pc += 4;
-                }
-                0x2E => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x24 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let data = self.pop()?;
+
+                self.store_u32(addr, data)?;
+
+                self.pc += 4;
+            }
+            0x25 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let data = self.pop()?;
 
-                    let data = self.stack.pop_back().unwrap();
+                self.store_u32(addr, data)?;
 
-                    self.memory[addr as usize] = (data & 0xFF) as u8;
+                self.pc += 4;
+            }
+            0x26 => {}
+            0x27 => {}
+            0x28 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                self.reserve(addr, 1)?;
+
+                self.pc += 4;
+            }
+            0x29 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let data = self.load_u8(addr)? as u32;
+
+                self.stack.push_back(data);
+
+                self.pc += 4;
+            }
+            0x2A => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
+                let data = self.pop()?;
+
+                self.store_u8(addr, data as u8)?;
+
+                self.pc += 4;
+            }
+            0x2C => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                self.reserve(addr, 1)?;
+
+                self.pc += 4;
+            }
+            0x2D => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let data = self.load_u8(addr)? as u32;
+
+                self.stack.push_back(data);
+
+                self.pc += 4;
+            }
+            0x2E => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let data = self.pop()?;
+
+                self.store_u8(addr, data as u8)?;
+
+                self.pc += 4;
+            }
+            0x30 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = a.wrapping_add(b);
+                self.stack.push_back(res);
+            }
+            0x31 => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = a + b;
+                let res = u32::from_be_bytes(res.to_be_bytes());
+                self.stack.push_back(res);
+            }
+            0x32 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = a.wrapping_sub(b);
+                self.stack.push_back(res);
+            }
+            0x33 => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = a - b;
+                let res = u32::from_be_bytes(res.to_be_bytes());
+                self.stack.push_back(res);
+            }
+            0x34 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = a.wrapping_mul(b);
+                self.stack.push_back(res);
+            }
+            0x35 => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = a * b;
+                let res = u32::from_be_bytes(res.to_be_bytes());
+                self.stack.push_back(res);
+            }
+            0x36 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = a.checked_div(b).ok_or(Trap::DivisionByZero)?;
+                self.stack.push_back(res);
+            }
+            0x37 => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = a / b;
+                let res = u32::from_be_bytes(res.to_be_bytes());
+                self.stack.push_back(res);
+            }
+            0x38 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = a.wrapping_add(b);
+                self.stack.push_back(res);
+            }
+            0x39 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = a.wrapping_sub(b);
+                self.stack.push_back(res);
+            }
+            // Field access at a constant offset from a base address, given as
+            // an operand by 0x40-0x43 and popped by 0x44-0x47. Stores pop the
+            // value after the base.
+            0x40 => {
+                let base = opcode::operand_u32(&self.byte_code, self.pc);
+                let offset = opcode::operand_u32(&self.byte_code, self.pc + 4);
+                let addr = field_address(base, offset)?;
+                let data = self.load_u32(addr)?;
+                self.stack.push_back(data);
+
+                self.pc += 8;
+            }
+            0x41 => {
+                let base = opcode::operand_u32(&self.byte_code, self.pc);
+                let offset = opcode::operand_u32(&self.byte_code, self.pc + 4);
+                let addr = field_address(base, offset)?;
+                let data = self.pop()?;
+                self.store_u32(addr, data)?;
+
+                self.pc += 8;
+            }
+            0x42 => {
+                let base = opcode::operand_u32(&self.byte_code, self.pc);
+                let offset = opcode::operand_u32(&self.byte_code, self.pc + 4);
+                let addr = field_address(base, offset)?;
+                let data = self.load_u8(addr)? as u32;
+                self.stack.push_back(data);
+
+                self.pc += 8;
+            }
+            0x43 => {
+                let base = opcode::operand_u32(&self.byte_code, self.pc);
+                let offset = opcode::operand_u32(&self.byte_code, self.pc + 4);
+                let addr = field_address(base, offset)?;
+                let data = self.pop()?;
+                self.store_u8(addr, data as u8)?;
+
+                self.pc += 8;
+            }
+            0x44 => {
+                let base = self.pop()?;
+                let offset = opcode::operand_u32(&self.byte_code, self.pc);
+                let addr = field_address(base, offset)?;
+                let data = self.load_u32(addr)?;
+                self.stack.push_back(data);
+
+                self.pc += 4;
+            }
+            0x45 => {
+                let base = self.pop()?;
+                let offset = opcode::operand_u32(&self.byte_code, self.pc);
+                let addr = field_address(base, offset)?;
+                let data = self.pop()?;
+                self.store_u32(addr, data)?;
+
+                self.pc += 4;
+            }
+            0x46 => {
+                let base = self.pop()?;
+                let offset = opcode::operand_u32(&self.byte_code, self.pc);
+                let addr = field_address(base, offset)?;
+                let data = self.load_u8(addr)? as u32;
+                self.stack.push_back(data);
+
+                self.pc += 4;
+            }
+            0x47 => {
+                let base = self.pop()?;
+                let offset = opcode::operand_u32(&self.byte_code, self.pc);
+                let addr = field_address(base, offset)?;
+                let data = self.pop()?;
+                self.store_u8(addr, data as u8)?;
+
+                self.pc += 4;
+            }
+            // Bulk memory operations on `len` bytes, popped after the
+            // addresses: copy (dst, src, len), fill (dst, byte, len) and
+            // compare (a, b, len). Every range is checked before any byte is
+            // written, and copies behave as if through a temporary buffer, so
+            // overlapping ranges are allowed.
+            0x48 => {
+                let len = self.pop()? as usize;
+                let src = self.pop()?;
+                let dst = self.pop()?;
+
+                let src = self.memory_range(src, len)?;
+                let dst = self.memory_range(dst, len)?;
+                self.memory.copy_within(src, dst.start);
+                self.note_store(dst);
+            }
+            0x49 => {
+                let len = self.pop()? as usize;
+                let byte = self.pop()? as u8;
+                let dst = self.pop()?;
+
+                let dst = self.memory_range(dst, len)?;
+                self.memory[dst.clone()].fill(byte);
+                self.note_store(dst);
+            }
+            // Pushes -1, 0 or 1 as the first range compares less than, equal
+            // to or greater than the second, byte by byte.
+            0x4A => {
+                let len = self.pop()? as usize;
+                let b = self.pop()?;
+                let a = self.pop()?;
+
+                let a = &self.memory[self.memory_range(a, len)?];
+                let b = &self.memory[self.memory_range(b, len)?];
+                let res = a.cmp(b) as i32;
+                self.stack.push_back(res as u32);
+            }
+            0x50 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let b = self.pop()?;
+
+                self.branch_taken = b != 0;
+                if self.branch_taken {
+                    self.pc = jump_target(addr)?;
+                } else {
                     self.pc += 4;
                 }
-                0x30 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = a + b;
-                    self.stack.push_back(res);
-                }
-                0x31 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = a + b;
-                    let res = u32::from_be_bytes(res.to_be_bytes());
-                    self.stack.push_back(res);
-                }
-                0x32 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = a - b;
-                    self.stack.push_back(res);
-                }
-                0x33 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = a - b;
-                    let res = u32::from_be_bytes(res.to_be_bytes());
-                    self.stack.push_back(res);
-                }
-                0x34 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = a * b;
-                    self.stack.push_back(res);
-                }
-                0x35 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = a * b;
-                    let res = u32::from_be_bytes(res.to_be_bytes());
-                    self.stack.push_back(res);
-                }
-                0x36 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = a / b;
-                    self.stack.push_back(res);
-                }
-                0x37 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = a / b;
-                    let res = u32::from_be_bytes(res.to_be_bytes());
-                    self.stack.push_back(res);
-                }
-                0x38 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = a + b;
-                    self.stack.push_back(res);
-                }
-                0x39 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = a - b;
-                    self.stack.push_back(res);
-                }
-                0x50 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
-
-                    let b = self.stack.pop_back().unwrap();
-
-                    if b != 0 {
-                        self.pc = addr as usize;
-                        self.pc -= 1;
-                    } else {
-                        self.pc += 4;
-                    }
-                }
-                0x51 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
-
-                    let b = self.stack.pop_back().unwrap();
-
-                    if b == 0 {
-                        self.pc = addr as usize;
-                        self.pc -= 1;
-                    } else {
-                        self.pc += 4;
-                    }
-                }
-                0x52 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = if a == b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x53 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = if a != b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x54 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = if a < b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x55 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = if a <= b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x56 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = if a > b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x57 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = if a >= b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x58 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = if (a != 0) && (b != 0) { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x59 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    let res = if (a != 0) || (b != 0) { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x5A => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
-
-                    self.pc = addr as usize;
-                    self.pc -= 1;
+            }
+            0x51 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let b = self.pop()?;
+
+                self.branch_taken = b == 0;
+                if self.branch_taken {
+                    self.pc = jump_target(addr)?;
+                } else {
+                    self.pc += 4;
                 }
-                0x5B => {
-                    let res = self.stack.pop_back().unwrap();
+            }
+            0x52 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = if a == b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x53 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = if a != b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x54 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = if a < b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x55 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = if a <= b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x56 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = if a > b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x57 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = if a >= b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x58 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = if (a != 0) && (b != 0) { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x59 => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let res = if (a != 0) || (b != 0) { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x5A => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    match self.stack.pop_back() {
-                        Some(i) => {
-                            self.pc = i as usize - 1;
-                        }
-                        None => {
-                            self.pc = self.byte_code.len();
-                        }
-                    }
+                self.pc = jump_target(addr)?;
+            }
+            0x5B => {
+                let res = self.pop()?;
 
-                    self.stack.push_back(res);
-                }
-                0x5C => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = if a == b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x5D => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = if a != b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x5E => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = if a < b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x5F => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = if a <= b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x60 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = if a > b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x61 => {
-                    let b = self.stack.pop_back().unwrap();
-                    let b = f32::from_be_bytes(b.to_be_bytes());
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
-                    let res = if a >= b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x62 => {
-                    let b = self.stack.pop_back().unwrap() == 1;
-                    let a = self.stack.pop_back().unwrap() == 1;
-                    let res = if a == b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x63 => {
-                    let b = self.stack.pop_back().unwrap() == 1;
-                    let a = self.stack.pop_back().unwrap() == 1;
-                    let res = if a != b { 1 } else { 0 };
-                    self.stack.push_back(res);
-                }
-                0x64 => match self.stack.pop_back() {
+                match self.stack.pop_back() {
                     Some(i) => {
-                        self.pc = i as usize - 1;
+                        self.pc = jump_target(i)?;
                     }
                     None => {
                         self.pc = self.byte_code.len();
                     }
-                },
-                0x80 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
-
-                    let type_len: u32 = self.byte_code[self.pc + 5] as u32;
-
-                    let arr_len: u32 = ((self.byte_code[self.pc + 6] as u32) << 24)
-                        | ((self.byte_code[self.pc + 7] as u32) << 16)
-                        | ((self.byte_code[self.pc + 8] as u32) << 8)
-                        | (self.byte_code[self.pc + 9] as u32);
+                }
 
-                    if addr + type_len * arr_len > self.memory.len() as u32 {
-                        let addition = addr + type_len * arr_len - self.memory.len() as u32;
+                self.stack.push_back(res);
+            }
+            0x5C => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = if a == b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x5D => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = if a != b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x5E => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = if a < b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x5F => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = if a <= b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x60 => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = if a > b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x61 => {
+                let b = self.pop()?;
+                let b = f32::from_be_bytes(b.to_be_bytes());
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                let res = if a >= b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x62 => {
+                let b = self.pop()? == 1;
+                let a = self.pop()? == 1;
+                let res = if a == b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x63 => {
+                let b = self.pop()? == 1;
+                let a = self.pop()? == 1;
+                let res = if a != b { 1 } else { 0 };
+                self.stack.push_back(res);
+            }
+            0x64 => match self.stack.pop_back() {
+                Some(i) => {
+                    self.pc = jump_target(i)?;
+                }
+                None => {
+                    self.pc = self.byte_code.len();
+                }
+            },
+            0x80 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
+
+                let type_len: u32 = self.byte_code[self.pc + 5] as u32;
+
+                let arr_len: u32 = ((self.byte_code[self.pc + 6] as u32) << 24)
+                    | ((self.byte_code[self.pc + 7] as u32) << 16)
+                    | ((self.byte_code[self.pc + 8] as u32) << 8)
+                    | (self.byte_code[self.pc + 9] as u32);
+
+                let len = type_len.checked_mul(arr_len).ok_or(Trap::AddressOverflow)?;
+                self.reserve(addr, len)?;
+                self.arrays.insert(
+                    addr,
+                    ArrayShape {
+                        elem_size: type_len,
+                        dims: vec![arr_len],
+                    },
+                );
+
+                self.pc += 9;
+            }
+            // Declares an array with `rank` dimensions, whose lengths are
+            // popped from the stack, outermost pushed first.
+            0x81 => {
+                let addr = opcode::operand_u32(&self.byte_code, self.pc);
+                let elem_size = self.byte_code[self.pc + 5] as u32;
+                let rank = self.byte_code[self.pc + 6] as usize;
+
+                if elem_size != 1 && elem_size != 4 {
+                    return Err(Trap::InvalidElementSize { size: elem_size });
+                }
 
-                        for _ in 0..addition {
-                            self.memory.push(0);
-                        }
-                    }
+                let dims = self.pop_many(rank)?;
+                let len = dims
+                    .iter()
+                    .try_fold(elem_size, |len, &dim| len.checked_mul(dim))
+                    .ok_or(Trap::AddressOverflow)?;
+                self.reserve(addr, len)?;
+                self.arrays.insert(addr, ArrayShape { elem_size, dims });
 
-                    self.pc += 9;
-                }
-                0x82 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 6;
+            }
+            0x82 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let idx = self.stack.pop_back().unwrap();
+                let idx = self.pop()?;
 
-                    let addr = addr + 4 * idx;
+                let addr = self.element_address(addr, idx, 4)?;
 
-                    let data = (self.memory[addr as usize] as u32) << 24
-                        | (self.memory[addr as usize + 1] as u32) << 16
-                        | (self.memory[addr as usize + 2] as u32) << 8
-                        | (self.memory[addr as usize + 3] as u32);
+                let data = self.load_u32(addr)?;
 
-                    self.stack.push_back(data);
+                self.stack.push_back(data);
 
-                    self.pc += 4;
-                }
-                0x83 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x83 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let idx = self.stack.pop_back().unwrap();
+                let idx = self.pop()?;
 
-                    let addr = addr + 4 * idx;
+                let addr = self.element_address(addr, idx, 4)?;
 
-                    let data = (self.memory[addr as usize] as u32) << 24
-                        | (self.memory[addr as usize + 1] as u32) << 16
-                        | (self.memory[addr as usize + 2] as u32) << 8
-                        | (self.memory[addr as usize + 3] as u32);
+                let data = self.load_u32(addr)?;
 
-                    self.stack.push_back(data);
+                self.stack.push_back(data);
 
-                    self.pc += 4;
-                }
-                0x84 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x84 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let idx = self.stack.pop_back().unwrap();
+                let idx = self.pop()?;
 
-                    let addr = addr + idx;
+                let addr = self.element_address(addr, idx, 1)?;
 
-                    let data = self.memory[addr as usize] as u32;
+                let data = self.load_u8(addr)? as u32;
 
-                    self.stack.push_back(data);
+                self.stack.push_back(data);
 
-                    self.pc += 4;
-                }
-                0x85 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x85 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let idx = self.stack.pop_back().unwrap();
+                let idx = self.pop()?;
 
-                    let addr = addr + idx;
+                let addr = self.element_address(addr, idx, 1)?;
 
-                    let data = self.memory[addr as usize] as u32;
+                let data = self.load_u8(addr)? as u32;
 
-                    self.stack.push_back(data);
+                self.stack.push_back(data);
 
-                    self.pc += 4;
-                }
-                0x87 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            // Multi-dimensional load and store pop one index per dimension of
+            // the array at `addr`, outermost pushed first. The store then pops
+            // the value.
+            0x86 => {
+                let addr = opcode::operand_u32(&self.byte_code, self.pc);
+
+                let (addr, elem_size) = self.shaped_address(addr)?;
+                // 0x80 accepts any element size, but these only move bytes
+                // and words.
+                let data = match elem_size {
+                    1 => self.load_u8(addr)? as u32,
+                    4 => self.load_u32(addr)?,
+                    size => return Err(Trap::InvalidElementSize { size }),
+                };
+
+                self.stack.push_back(data);
+
+                self.pc += 4;
+            }
+            0x87 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let idx = self.stack.pop_back().unwrap();
-                    let data = self.stack.pop_back().unwrap();
+                let idx = self.pop()?;
+                let data = self.pop()?;
 
-                    let addr = addr + idx * 4;
+                let addr = self.element_address(addr, idx, 4)?;
 
-                    self.memory[addr as usize] = ((data & 0xFF000000) >> 24) as u8;
-                    self.memory[addr as usize + 1] = ((data & 0x00FF0000) >> 16) as u8;
-                    self.memory[addr as usize + 2] = ((data & 0x0000FF00) >> 8) as u8;
-                    self.memory[addr as usize + 3] = (data & 0x000000FF) as u8;
+                self.store_u32(addr, data)?;
 
-                    self.pc += 4;
-                }
-                0x88 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x88 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let idx = self.stack.pop_back().unwrap();
+                let idx = self.pop()?;
 
-                    let data = self.stack.pop_back().unwrap();
-                    let addr = addr + idx * 4;
+                let data = self.pop()?;
+                let addr = self.element_address(addr, idx, 4)?;
 
-                    self.memory[addr as usize] = ((data & 0xFF000000) >> 24) as u8;
-                    self.memory[addr as usize + 1] = ((data & 0x00FF0000) >> 16) as u8;
-                    self.memory[addr as usize + 2] = ((data & 0x0000FF00) >> 8) as u8;
-                    self.memory[addr as usize + 3] = (data & 0x000000FF) as u8;
+                self.store_u32(addr, data)?;
 
-                    self.pc += 4;
-                }
-                0x89 => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x89 => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let idx = self.stack.pop_back().unwrap();
+                let idx = self.pop()?;
 
-                    let data = self.stack.pop_back().unwrap();
-                    let addr = addr + idx;
+                let data = self.pop()?;
+                let addr = self.element_address(addr, idx, 1)?;
 
-                    self.memory[addr as usize] = data as u8;
+                self.store_u8(addr, data as u8)?;
 
-                    self.pc += 4;
-                }
-                0x8A => {
-                    let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
-                        | ((self.byte_code[self.pc + 2] as u32) << 16)
-                        | ((self.byte_code[self.pc + 3] as u32) << 8)
-                        | (self.byte_code[self.pc + 4] as u32);
+                self.pc += 4;
+            }
+            0x8A => {
+                let addr: u32 = ((self.byte_code[self.pc + 1] as u32) << 24)
+                    | ((self.byte_code[self.pc + 2] as u32) << 16)
+                    | ((self.byte_code[self.pc + 3] as u32) << 8)
+                    | (self.byte_code[self.pc + 4] as u32);
 
-                    let idx = self.stack.pop_back().unwrap();
+                let idx = self.pop()?;
 
-                    let data = self.stack.pop_back().unwrap();
-                    let addr = addr + idx;
+                let data = self.pop()?;
+                let addr = self.element_address(addr, idx, 1)?;
 
-                    self.memory[addr as usize] = data as u8;
+                self.store_u8(addr, data as u8)?;
 
-                    self.pc += 4;
+                self.pc += 4;
+            }
+            0x8B => {
+                let addr = opcode::operand_u32(&self.byte_code, self.pc);
+
+                let (addr, elem_size) = self.shaped_address(addr)?;
+                let data = self.pop()?;
+                match elem_size {
+                    1 => self.store_u8(addr, data as u8)?,
+                    4 => self.store_u32(addr, data)?,
+                    size => return Err(Trap::InvalidElementSize { size }),
                 }
-                0x90 => {
-                    let a = self.stack.pop_back().unwrap();
-                    let a = i32::from_be_bytes(a.to_be_bytes());
+
+                self.pc += 4;
+            }
+            0x90 => {
+                let a = self.pop()?;
+                let a = i32::from_be_bytes(a.to_be_bytes());
+                if !self.muted {
                     print!("{a}");
                 }
-                0x91 => {
-                    let a = self.stack.pop_back().unwrap();
-                    let a = f32::from_be_bytes(a.to_be_bytes());
+            }
+            0x91 => {
+                let a = self.pop()?;
+                let a = f32::from_be_bytes(a.to_be_bytes());
+                if !self.muted {
                     print!("{a}");
                 }
-                0x92 => {
-                    let a = self.stack.pop_back().unwrap() != 0;
+            }
+            0x92 => {
+                let a = self.pop()? != 0;
+                if !self.muted {
                     print!("{}", if a { "true" } else { "false" });
                 }
-                0x93 => {
-                    let a = self.stack.pop_back().unwrap();
-                    let a = (a as u8) as char;
+            }
+            0x93 => {
+                let a = self.pop()?;
+                let a = (a as u8) as char;
+                if !self.muted {
                     print!("{a}");
                 }
-                _ => {
-                    println!("unrecognized opcode {opcode} !!! halting");
-                    break;
+            }
+            // The input opcodes push what they read, then a status code from
+            // `input`.
+            0x94 => {
+                let (value, status) = match self.read_token()? {
+                    Some(token) => match token.parse::<i32>() {
+                        Ok(a) => (a as u32, input::READ_OK),
+                        Err(_) => (0, input::READ_INVALID),
+                    },
+                    None => (0, input::READ_EOF),
+                };
+                self.stack.push_back(value);
+                self.stack.push_back(status);
+            }
+            0x95 => {
+                let (value, status) = match self.read_token()? {
+                    Some(token) => match token.parse::<f32>() {
+                        Ok(a) => (u32::from_be_bytes(a.to_be_bytes()), input::READ_OK),
+                        Err(_) => (0, input::READ_INVALID),
+                    },
+                    None => (0, input::READ_EOF),
+                };
+                self.stack.push_back(value);
+                self.stack.push_back(status);
+            }
+            0x96 => {
+                let byte = self.input.read_byte().map_err(input_error)?;
+                self.stack.push_back(byte.unwrap_or(0) as u32);
+                self.stack.push_back(match byte {
+                    Some(_) => input::READ_OK,
+                    None => input::READ_EOF,
+                });
+            }
+            // Reads a line into the `len` bytes at `addr` and pushes how many
+            // were written. The rest of a longer line is dropped.
+            0x97 => {
+                let len = self.pop()?;
+                let addr = self.pop()?;
+                // Checked first, so that a bad buffer consumes no input.
+                let range = self.memory_range(addr, len as usize)?;
+                let line = self.input.read_line().map_err(input_error)?;
+
+                let written = line.as_ref().map_or(0, |l| l.len().min(len as usize));
+                if let Some(line) = &line {
+                    let range = range.start..range.start + written;
+                    self.memory[range.clone()].copy_from_slice(&line[..written]);
+                    self.note_store(range);
+                }
+                self.stack.push_back(written as u32);
+                self.stack.push_back(match line {
+                    Some(_) => input::READ_OK,
+                    None => input::READ_EOF,
+                });
+            }
+            // The file opcodes push a status code from `files` last, and only
+            // trap on stack or memory errors or when a node runs out of
+            // files to open. Run again, they repeat their
+            // recorded outcome and leave the files alone.
+            0xC0 => {
+                let mode = self.pop()?;
+                let len = self.pop()?;
+                let addr = self.pop()?;
+                let range = self.memory_range(addr, len as usize)?;
+
+                let outcome = match self.recorded_outcome() {
+                    Some(outcome) => outcome,
+                    None => {
+                        let (handle, status) = match self.files.open(&self.memory[range], mode)? {
+                            Ok(handle) => (handle, files::FILE_OK),
+                            Err(status) => (0, status),
+                        };
+                        Outcome {
+                            values: vec![handle, status],
+                            data: vec![],
+                        }
+                    }
+                };
+                self.apply_outcome(outcome);
+            }
+            0xC1 => {
+                let len = self.pop()?;
+                let addr = self.pop()?;
+                let handle = self.pop()?;
+                let range = self.memory_range(addr, len as usize)?;
+
+                let outcome = match self.recorded_outcome() {
+                    Some(outcome) => {
+                        let count = outcome.data.len().min(range.len());
+                        self.memory[range.start..range.start + count]
+                            .copy_from_slice(&outcome.data[..count]);
+                        outcome
+                    }
+                    None => {
+                        let buf = &mut self.memory[range.clone()];
+                        let (count, status) = match self.files.read(handle, buf) {
+                            Ok(count) => (count, files::FILE_OK),
+                            Err(status) => (0, status),
+                        };
+                        Outcome {
+                            values: vec![count as u32, status],
+                            data: buf[..count].to_vec(),
+                        }
+                    }
+                };
+                let count = outcome.data.len().min(range.len());
+                self.note_store(range.start..range.start + count);
+                self.apply_outcome(outcome);
+            }
+            0xC2 => {
+                let len = self.pop()?;
+                let addr = self.pop()?;
+                let handle = self.pop()?;
+                let range = self.memory_range(addr, len as usize)?;
+
+                let outcome = self.recorded_outcome().unwrap_or_else(|| {
+                    Outcome::value(match self.files.write(handle, &self.memory[range]) {
+                        Ok(()) => files::FILE_OK,
+                        Err(status) => status,
+                    })
+                });
+                self.apply_outcome(outcome);
+            }
+            0xC3 => {
+                let handle = self.pop()?;
+
+                let outcome = self.recorded_outcome().unwrap_or_else(|| {
+                    Outcome::value(match self.files.close(handle) {
+                        Ok(()) => files::FILE_OK,
+                        Err(status) => status,
+                    })
+                });
+                self.apply_outcome(outcome);
+            }
+            0xD0 => {
+                let outcome = self
+                    .recorded_outcome()
+                    .unwrap_or_else(|| Outcome::value(self.clock.ticks() as u32));
+                self.apply_outcome(outcome);
+            }
+            0xD1 => {
+                // Left on the stack until the sleep completes, so a node
+                // stopped by a time limit can retry it.
+                let ms = self.stack.back().copied().ok_or(Trap::StackUnderflow)?;
+                let (until, trap) = self.sleep_deadline().unzip();
+                if self.clock.sleep(ms as u64, until) {
+                    return Err(trap.unwrap());
+                }
+                self.stack.pop_back();
+            }
+            0xD2 => {
+                let bound = self.pop()?;
+                self.stack.push_back(self.rng.below(bound));
+            }
+            0xD3 => self.stack.push_back(self.rng.unit_f32().to_bits()),
+            0xA0 => {
+                let target = &self.strings[opcode::operand_u32(&self.byte_code, self.pc) as usize];
+
+                let Some(link) = self.links.iter().find(|l| l.is_named(target)) else {
+                    return Err(Trap::NotANeighbor {
+                        target: target.clone(),
+                    });
+                };
+
+                let mut mailbox = link.mailbox.lock().unwrap();
+                if link.is_full(&mailbox, self.id) {
+                    return Ok(Flow::Blocked);
+                }
+
+                let value = self.stack.pop_back().ok_or(Trap::StackUnderflow)?;
+                mailbox.push_back(Message {
+                    from: self.id,
+                    value,
+                });
+
+                self.pc += 4;
+            }
+            0xA1 => {
+                let message = self.mailbox.lock().unwrap().pop_front();
+
+                match message {
+                    Some(message) => self.stack.push_back(message.value),
+                    None => return Ok(Flow::Blocked),
+                }
+            }
+            0xB0 => {
+                let index = opcode::operand_u32(&self.byte_code, self.pc) as usize;
+                let outcome = match self.recorded_outcome() {
+                    Some(outcome) => {
+                        self.pop_many(self.imports[index].arity as usize)?;
+                        outcome
+                    }
+                    None => {
+                        let Some(host) = self.host_fns[index].clone() else {
+                            return Err(Trap::UnresolvedImport {
+                                function: self.import_name(&self.imports[index]).to_string(),
+                            });
+                        };
+
+                        let args = self.pop_many(host.arity() as usize)?;
+                        let value = host.call(&args).map_err(|message| Trap::HostError {
+                            function: host.name().to_string(),
+                            message,
+                        })?;
+                        Outcome::value(value)
+                    }
+                };
+                self.apply_outcome(outcome);
+
+                self.pc += 4;
+            }
+            _ => return Err(Trap::InvalidOpcode { opcode }),
+        }
+        self.pc += 1;
+        self.steps += 1;
+
+        Ok(Flow::Next)
+    }
+
+    /// The next whitespace-separated token of the input, if it is valid
+    /// UTF-8. Tokens that are not count as unparseable.
+    fn read_token(&mut self) -> Result<Option<String>, Trap> {
+        let token = self.input.read_token().map_err(input_error)?;
+        Ok(token.map(|t| String::from_utf8(t).unwrap_or_default()))
+    }
+
+    /// The outcome the next instruction had when it ran before, if it is
+    /// running again.
+    fn recorded_outcome(&self) -> Option<Outcome> {
+        self.outcomes.as_ref()?.get(&self.steps).cloned()
+    }
+
+    /// Pushes the values of `outcome`, and keeps it for the journal and for
+    /// running the instruction again.
+    fn apply_outcome(&mut self, outcome: Outcome) {
+        self.stack.extend(outcome.values.iter().copied());
+        if let Some(outcomes) = &mut self.outcomes {
+            outcomes.insert(self.steps, outcome.clone());
+        }
+        if self.journal.is_some() {
+            self.outcome = Some(outcome);
+        }
+    }
+
+    fn pop(&mut self) -> Result<u32, Trap> {
+        self.stack.pop_back().ok_or(Trap::StackUnderflow)
+    }
+
+    /// Pops `n` values, returned in the order they were pushed.
+    fn pop_many(&mut self, n: usize) -> Result<Vec<u32>, Trap> {
+        if self.stack.len() < n {
+            return Err(Trap::StackUnderflow);
+        }
+        let values = self.stack.split_off(self.stack.len() - n);
+        Ok(values.into_iter().collect())
+    }
+
+    fn memory_range(&self, addr: u32, len: usize) -> Result<Range<usize>, Trap> {
+        let start = addr as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.memory.len() => Ok(start..end),
+            _ => Err(Trap::MemoryOutOfBounds {
+                addr,
+                size: self.memory.len(),
+            }),
+        }
+    }
+
+    /// Address of element `idx` of an array of `size`-byte elements at `base`.
+    /// If the array was declared with `0x80` or `0x81`, `idx` is checked
+    /// against its total number of elements unless bounds checks are turned
+    /// off.
+    fn element_address(&self, base: u32, idx: u32, size: u32) -> Result<u32, Trap> {
+        if self.bounds_checks {
+            if let Some(len) = self.arrays.get(&base).map(ArrayShape::len) {
+                if idx >= len {
+                    return Err(Trap::IndexOutOfBounds {
+                        base,
+                        index: idx,
+                        len,
+                    });
                 }
             }
-            self.pc += 1;
         }
-        println!("END PROGRAM OUTPUT ----");
-        println!("{:?}", self.stack);
-        println!("{:?}", self.memory);
+
+        idx.checked_mul(size)
+            .and_then(|offset| base.checked_add(offset))
+            .ok_or(Trap::AddressOverflow)
     }
+
+    /// Pops one index per dimension of the array declared at `base` and
+    /// returns the row-major address of that element along with its size.
+    fn shaped_address(&mut self, base: u32) -> Result<(u32, u32), Trap> {
+        let shape = self
+            .arrays
+            .get(&base)
+            .cloned()
+            .ok_or(Trap::UndeclaredArray { base })?;
+
+        let indices = self.pop_many(shape.dims.len())?;
+        let mut offset: u32 = 0;
+        for (dim, (&index, &len)) in indices.iter().zip(&shape.dims).enumerate() {
+            if self.bounds_checks && index >= len {
+                return Err(Trap::DimensionOutOfBounds {
+                    base,
+                    dim,
+                    index,
+                    len,
+                });
+            }
+            offset = offset
+                .checked_mul(len)
+                .and_then(|offset| offset.checked_add(index))
+                .ok_or(Trap::AddressOverflow)?;
+        }
+
+        let addr = offset
+            .checked_mul(shape.elem_size)
+            .and_then(|offset| base.checked_add(offset))
+            .ok_or(Trap::AddressOverflow)?;
+        Ok((addr, shape.elem_size))
+    }
+
+    /// Grows memory in one step so that `len` bytes starting at `addr` exist,
+    /// within the node's memory limit.
+    fn reserve(&mut self, addr: u32, len: u32) -> Result<(), Trap> {
+        let end = addr.checked_add(len).ok_or(Trap::AddressOverflow)?;
+        let limit = self.limits.memory.unwrap_or(DEFAULT_MEMORY_LIMIT);
+        if end > limit {
+            return Err(Trap::MemoryLimit {
+                requested: end,
+                limit,
+            });
+        }
+        if end as usize > self.memory.len() {
+            self.memory.resize(end as usize, 0);
+        }
+        Ok(())
+    }
+
+    fn load_u32(&self, addr: u32) -> Result<u32, Trap> {
+        let range = self.memory_range(addr, 4)?;
+        Ok(u32::from_be_bytes(self.memory[range].try_into().unwrap()))
+    }
+
+    fn store_u32(&mut self, addr: u32, data: u32) -> Result<(), Trap> {
+        let range = self.memory_range(addr, 4)?;
+        self.memory[range.clone()].copy_from_slice(&data.to_be_bytes());
+        self.note_store(range);
+        Ok(())
+    }
+
+    fn load_u8(&self, addr: u32) -> Result<u8, Trap> {
+        let range = self.memory_range(addr, 1)?;
+        Ok(self.memory[range.start])
+    }
+
+    fn store_u8(&mut self, addr: u32, data: u8) -> Result<(), Trap> {
+        let range = self.memory_range(addr, 1)?;
+        self.memory[range.start] = data;
+        self.note_store(range);
+        Ok(())
+    }
+
+    fn note_store(&mut self, range: Range<usize>) {
+        if let Some(stores) = &mut self.stores {
+            stores.push(range);
+        }
+    }
+}
+
+fn field_address(base: u32, offset: u32) -> Result<u32, Trap> {
+    base.checked_add(offset).ok_or(Trap::AddressOverflow)
+}
+
+fn check_memory_size(node: &str, size: u32, limits: Limits) -> Result<(), Error> {
+    let limit = limits.memory.unwrap_or(DEFAULT_MEMORY_LIMIT);
+    if size > limit {
+        return Err(Error::MemoryLimit {
+            node: node.to_string(),
+            requested: size,
+            limit,
+        });
+    }
+    Ok(())
+}
+
+/// Jumps set the pc one short of the target, since every instruction ends by
+/// stepping past its opcode.
+fn jump_target(addr: u32) -> Result<usize, Trap> {
+    (addr as usize)
+        .checked_sub(1)
+        .ok_or(Trap::InvalidJump { addr })
+}
+
+fn input_error(e: io::Error) -> Trap {
+    Trap::InputError {
+        message: e.to_string(),
+    }
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![];
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|source| Error::Io {
+            path: path.to_string(),
+            source,
+        })?;
+
+    Ok(bytes)
 }
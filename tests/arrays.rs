@@ -0,0 +1,100 @@
+mod common;
+
+use common::{single_node_project, Asm};
+use pndm::{
+    error::{Error, Trap},
+    node_file::NodeFile,
+    vm::VirtualMachine,
+};
+
+/// Runs `asm` as the only node, returning its stack and memory.
+fn run(name: &str, asm: &Asm, bounds_checks: bool) -> Result<(Vec<u32>, Vec<u8>), Error> {
+    let main = NodeFile {
+        code: asm.finish(),
+        memory_size: 16,
+        ..NodeFile::default()
+    };
+    let mut vm = VirtualMachine::new(&single_node_project(name, main)).unwrap();
+    vm.set_bounds_checks(bounds_checks);
+    vm.execute()?;
+
+    let main = vm.node("Main").unwrap();
+    Ok((
+        main.stack().iter().copied().collect(),
+        main.memory().to_vec(),
+    ))
+}
+
+#[test]
+fn indices_are_checked_against_declared_lengths() {
+    // Declares two words at 0, then stores 7 at index 2 and loads it back.
+    let mut asm = Asm::new();
+    asm.op_u32(0x80, 0)
+        .op_u32(4, 2)
+        .op_u32(0x10, 7)
+        .op_u32(0x10, 2)
+        .op_u32(0x87, 0)
+        .op_u32(0x10, 2)
+        .op_u32(0x82, 0);
+
+    let err = run("bounds-on", &asm, true).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Trap {
+                trap: Trap::IndexOutOfBounds {
+                    base: 0,
+                    index: 2,
+                    len: 2
+                },
+                ..
+            }
+        ),
+        "{err}"
+    );
+
+    let (stack, memory) = run("bounds-off", &asm, false).unwrap();
+    assert_eq!(stack, vec![7]);
+    assert_eq!(memory[8..12], 7u32.to_be_bytes());
+
+    // Addresses that were never declared as arrays are not checked.
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 3).op_u32(0x10, 3).op_u32(0x89, 0);
+    let (_, memory) = run("bounds-undeclared", &asm, true).unwrap();
+    assert_eq!(memory[3], 3);
+}
+
+#[test]
+fn multi_dimensional_arrays_are_row_major() {
+    // A 2x3 array of words at 0, and a rank-0 array of one byte at 32.
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 2)
+        .op_u32(0x10, 3)
+        .op_u32(0x81, 0)
+        .op(4)
+        .op(2)
+        .op_u32(0x10, 11)
+        .op_u32(0x10, 0)
+        .op_u32(0x10, 1)
+        .op_u32(0x8B, 0)
+        .op_u32(0x10, 12)
+        .op_u32(0x10, 1)
+        .op_u32(0x10, 2)
+        .op_u32(0x8B, 0)
+        .op_u32(0x10, 1)
+        .op_u32(0x10, 2)
+        .op_u32(0x86, 0)
+        .op_u32(0x81, 32)
+        .op(1)
+        .op(0)
+        .op_u32(0x10, 9)
+        .op_u32(0x8B, 32)
+        .op_u32(0x86, 32);
+
+    let (stack, memory) = run("multi-dim", &asm, true).unwrap();
+    assert_eq!(stack, vec![12, 9]);
+    assert_eq!(memory.len(), 33);
+    assert_eq!(memory[4..8], 11u32.to_be_bytes());
+    assert_eq!(memory[20..24], 12u32.to_be_bytes());
+    assert_eq!(memory[32], 9);
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{single_node_project, Asm};
+use pndm::{
+    error::{Error, Trap},
+    node_file::NodeFile,
+    vm::VirtualMachine,
+};
+
+const DATA: &[u8] = b"abcdefgh\xff\x01";
+
+/// Pushes each of `operands` and then runs `opcode`.
+fn bulk(asm: &mut Asm, opcode: u8, operands: [u32; 3]) -> &mut Asm {
+    for operand in operands {
+        asm.op_u32(0x10, operand);
+    }
+    asm.op(opcode)
+}
+
+fn run(name: &str, asm: &Asm) -> (VirtualMachine, Result<(), Error>) {
+    let main = NodeFile {
+        code: asm.finish(),
+        memory_size: 16,
+        data: DATA.to_vec(),
+        ..NodeFile::default()
+    };
+    let mut vm = VirtualMachine::new(&single_node_project(name, main)).unwrap();
+    let result = vm.execute();
+    (vm, result)
+}
+
+#[test]
+fn copies_may_overlap_in_either_direction() {
+    let mut asm = Asm::new();
+    bulk(&mut asm, 0x48, [2, 0, 4]);
+    let (vm, result) = run("memcpy-forward", &asm);
+    result.unwrap();
+    assert_eq!(vm.node("Main").unwrap().memory()[..8], *b"ababcdgh");
+
+    bulk(&mut asm, 0x48, [0, 3, 4]);
+    bulk(&mut asm, 0x49, [12, b'z' as u32, 4]);
+    let (vm, result) = run("memcpy-backward", &asm);
+    result.unwrap();
+    let memory = vm.node("Main").unwrap().memory();
+    assert_eq!(memory[..8], *b"bcdgcdgh");
+    assert_eq!(memory[12..], *b"zzzz");
+
+    // Nothing is written when either range is out of bounds.
+    let mut asm = Asm::new();
+    bulk(&mut asm, 0x48, [0, 14, 4]);
+    let (vm, result) = run("memcpy-out-of-bounds", &asm);
+    let err = result.unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Trap {
+                trap: Trap::MemoryOutOfBounds { addr: 14, size: 16 },
+                ..
+            }
+        ),
+        "{err}"
+    );
+    assert_eq!(vm.node("Main").unwrap().memory()[..DATA.len()], *DATA);
+}
+
+#[test]
+fn compares_order_bytes_as_unsigned() {
+    let mut asm = Asm::new();
+    bulk(&mut asm, 0x4A, [0, 0, 8]);
+    bulk(&mut asm, 0x4A, [0, 1, 2]);
+    bulk(&mut asm, 0x4A, [1, 0, 2]);
+    bulk(&mut asm, 0x4A, [8, 9, 1]);
+    bulk(&mut asm, 0x4A, [3, 7, 0]);
+    let (vm, result) = run("memcmp", &asm);
+    result.unwrap();
+
+    let stack: Vec<i32> = vm
+        .node("Main")
+        .unwrap()
+        .stack()
+        .iter()
+        .map(|&v| v as i32)
+        .collect();
+    assert_eq!(stack, vec![0, -1, 1, 1, 0]);
+}
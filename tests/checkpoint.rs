@@ -0,0 +1,57 @@
+mod common;
+
+use common::{fold_project, node_states, Fold};
+use pndm::{snapshot::GraphCheckpoint, vm::VirtualMachine};
+
+const SLICE: u64 = 7;
+
+fn load(path: &str) -> VirtualMachine {
+    let mut vm = VirtualMachine::new(path).unwrap();
+    vm.set_slice(SLICE);
+    vm
+}
+
+#[test]
+fn restored_checkpoint_matches_uninterrupted_run() {
+    let path = fold_project("restored-checkpoint-matches", Fold::Ordered);
+
+    let mut vm = load(&path);
+    vm.execute().unwrap();
+    let expected = node_states(&vm);
+
+    let mut in_flight = false;
+    for steps in [1, 50, 333, 1234, 2000] {
+        let file = format!("{path}/checkpoint-{steps}.json");
+
+        let mut vm = load(&path);
+        vm.execute_with_checkpoint(steps, &file).unwrap();
+        assert_eq!(
+            node_states(&vm),
+            expected,
+            "run that took a checkpoint at {steps}"
+        );
+
+        let checkpoint = GraphCheckpoint::read(&file).unwrap();
+        in_flight |= checkpoint.nodes.iter().any(|n| !n.mailbox.is_empty());
+
+        let mut restored = load(&path);
+        restored.restore(checkpoint).unwrap();
+        restored.execute().unwrap();
+        assert_eq!(node_states(&restored), expected, "run restored at {steps}");
+    }
+
+    assert!(in_flight, "no checkpoint caught a message in flight");
+}
+
+#[test]
+fn restore_rejects_checkpoint_of_other_code() {
+    let path = fold_project("restore-rejects-other-code", Fold::Ordered);
+    let file = format!("{path}/checkpoint.json");
+
+    let mut vm = load(&path);
+    vm.execute_with_checkpoint(100, &file).unwrap();
+
+    let mut checkpoint = GraphCheckpoint::read(&file).unwrap();
+    checkpoint.nodes[0].code_hash ^= 1;
+    assert!(load(&path).restore(checkpoint).is_err());
+}
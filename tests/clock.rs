@@ -0,0 +1,210 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{temp_dir, write_project, Asm};
+use pndm::{error::Error, node_file::NodeFile, snapshot::GraphCheckpoint, vm::VirtualMachine};
+
+/// Rolls three dice and draws a float, then reads the clock on either side of
+/// a 250 ms sleep. Everything stays on the stack.
+fn clock_file() -> NodeFile {
+    let mut asm = Asm::new();
+    for _ in 0..3 {
+        asm.op_u32(0x10, 6).op(0xD2);
+    }
+    asm.op(0xD3).op(0xD0).op_u32(0x10, 250).op(0xD1).op(0xD0);
+
+    NodeFile {
+        code: asm.finish(),
+        ..NodeFile::default()
+    }
+}
+
+fn clock_project(name: &str) -> String {
+    let graph = r#"{ "entry": ["A", "B"], "nodes": { "A": {}, "B": {} } }"#;
+    let dir = write_project(name, graph, &[("A", clock_file()), ("B", clock_file())]);
+    dir.to_string_lossy().into_owned()
+}
+
+fn run(path: &str, seed: u64, threads: usize) -> Vec<Vec<u32>> {
+    let mut vm = VirtualMachine::new(path).unwrap();
+    vm.set_seed(seed);
+    vm.use_virtual_clock();
+    vm.set_threads(threads);
+    vm.execute().unwrap();
+    stacks(&vm)
+}
+
+fn stacks(vm: &VirtualMachine) -> Vec<Vec<u32>> {
+    vm.nodes()
+        .iter()
+        .map(|n| n.stack().iter().copied().collect())
+        .collect()
+}
+
+#[test]
+fn the_same_seed_gives_the_same_run() {
+    let path = clock_project("clock-seed");
+    let first = run(&path, 7, 1);
+    assert_eq!(run(&path, 7, 1), first);
+    assert_eq!(run(&path, 7, 4), first);
+    assert_ne!(run(&path, 8, 1), first);
+
+    let a = &first[0];
+    assert!(a[..3].iter().all(|&roll| roll < 6));
+    assert!((0.0..1.0).contains(&f32::from_bits(a[3])));
+    assert_eq!(a[4..], [0, 250]);
+    assert_ne!(first[0][..4], first[1][..4]);
+}
+
+#[test]
+fn checkpoints_keep_the_clock_and_random_state() {
+    let path = clock_project("clock-checkpoint");
+    let expected = run(&path, 7, 1);
+
+    let checkpoint = temp_dir("clock-checkpoint-file").join("checkpoint.json");
+    let checkpoint = checkpoint.to_string_lossy();
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.set_seed(7);
+    vm.use_virtual_clock();
+    vm.execute_with_checkpoint(12, &checkpoint).unwrap();
+
+    // The checkpoint says the clock is virtual.
+    let mut resumed = VirtualMachine::new(&path).unwrap();
+    resumed
+        .restore(GraphCheckpoint::read(&checkpoint).unwrap())
+        .unwrap();
+    resumed.execute().unwrap();
+    assert_eq!(stacks(&resumed), expected);
+}
+
+#[test]
+fn sleeps_end_at_the_time_limit() {
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 60_000).op(0xD1).op(0xD0);
+    let sleeper = || NodeFile {
+        code: asm.finish(),
+        ..NodeFile::default()
+    };
+
+    let graph = r#"{ "nodes": { "Main": {}, "Worker": {} }, "entry": "Main", "limits": { "time_ms": 50 } }"#;
+    let dir = write_project(
+        "clock-graph-limit",
+        graph,
+        &[("Main", sleeper()), ("Worker", sleeper())],
+    );
+    for threads in [1, 2] {
+        let started = Instant::now();
+        let mut vm = VirtualMachine::new(&dir.to_string_lossy()).unwrap();
+        vm.set_threads(threads);
+        let err = vm.execute().unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::TimeLimit {
+                    node: None,
+                    limit_ms: 50
+                }
+            ),
+            "{err}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    let graph = r#"{ "nodes": { "Main": { "limits": { "time_ms": 50 } } } }"#;
+    let dir = write_project("clock-node-limit", graph, &[("Main", sleeper())]);
+    let mut vm = VirtualMachine::new(&dir.to_string_lossy()).unwrap();
+    let err = vm.execute().unwrap_err();
+    assert!(
+        matches!(&err, Error::TimeLimit { node: Some(node), limit_ms: 50 } if node == "Main"),
+        "{err}"
+    );
+
+    // Virtual time is not bound by the limit.
+    let mut vm = VirtualMachine::new(&dir.to_string_lossy()).unwrap();
+    vm.use_virtual_clock();
+    vm.execute().unwrap();
+    assert_eq!(stacks(&vm), vec![vec![60_000]]);
+}
+
+#[test]
+fn checkpoints_do_not_restart_the_time_limit() {
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 200).op(0xD1).op_u32(0x10, 200).op(0xD1);
+    let file = NodeFile {
+        code: asm.finish(),
+        ..NodeFile::default()
+    };
+
+    let graph = r#"{ "nodes": { "Main": {} }, "limits": { "time_ms": 300 } }"#;
+    let dir = write_project("clock-checkpoint-limit", graph, &[("Main", file)]);
+    let checkpoint = temp_dir("clock-checkpoint-limit-out").join("graph.ckpt");
+
+    // The checkpoint falls between the two sleeps; together they overrun.
+    let mut vm = VirtualMachine::new(&dir.to_string_lossy()).unwrap();
+    let err = vm
+        .execute_with_checkpoint(3, &checkpoint.to_string_lossy())
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::TimeLimit {
+                node: None,
+                limit_ms: 300
+            }
+        ),
+        "{err}"
+    );
+    assert!(checkpoint.exists());
+}
+
+#[test]
+fn replays_read_the_recorded_clock() {
+    let graph = r#"{ "nodes": { "Main": {} } }"#;
+    let mut asm = Asm::new();
+    asm.op(0xD0).op_u32(0x10, 20).op(0xD1).op(0xD0);
+    let main = NodeFile {
+        code: asm.finish(),
+        ..NodeFile::default()
+    };
+    let dir = write_project("clock-replay", graph, &[("Main", main)]);
+    let path = dir.to_string_lossy();
+
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.record();
+    vm.execute().unwrap();
+    let mut log = vm.recording().unwrap();
+    assert!(!log.virtual_clock);
+    let [first, _, last] = log.events.as_mut_slice() else {
+        panic!("expected three events, got {:?}", log.events);
+    };
+    let recorded = last.outcome.clone().unwrap().values;
+    first.outcome.as_mut().unwrap().values = vec![12345];
+
+    let mut replayed = VirtualMachine::new(&path).unwrap();
+    replayed.replay(&log).unwrap();
+    assert_eq!(stacks(&replayed), vec![[vec![12345], recorded].concat()]);
+
+    // A virtual clock is restored as such, so the replay does not sleep.
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 60_000).op(0xD1).op(0xD0);
+    let main = NodeFile {
+        code: asm.finish(),
+        ..NodeFile::default()
+    };
+    let dir = write_project("clock-replay-virtual", graph, &[("Main", main)]);
+    let path = dir.to_string_lossy();
+
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.use_virtual_clock();
+    vm.record();
+    vm.execute().unwrap();
+    let log = vm.recording().unwrap();
+    assert!(log.virtual_clock);
+
+    let started = Instant::now();
+    let mut replayed = VirtualMachine::new(&path).unwrap();
+    replayed.replay(&log).unwrap();
+    assert_eq!(stacks(&replayed), vec![vec![60_000]]);
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
@@ -0,0 +1,245 @@
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use pndm::{
+    node_file::{Import, NodeFile},
+    vm::VirtualMachine,
+};
+
+/// Builds byte code with forward and backward jumps to named labels.
+#[derive(Default)]
+pub struct Asm {
+    code: Vec<u8>,
+    labels: HashMap<&'static str, u32>,
+    fixups: Vec<(usize, &'static str)>,
+}
+
+impl Asm {
+    pub fn new() -> Self {
+        // Jumps land at `addr - 1` before the pc advances, so nothing may jump
+        // to offset 0; start with a no-op.
+        let mut asm = Self::default();
+        asm.op(0x26);
+        asm
+    }
+
+    pub fn op(&mut self, opcode: u8) -> &mut Self {
+        self.code.push(opcode);
+        self
+    }
+
+    pub fn op_u32(&mut self, opcode: u8, operand: u32) -> &mut Self {
+        self.code.push(opcode);
+        self.code.extend_from_slice(&operand.to_be_bytes());
+        self
+    }
+
+    pub fn jump(&mut self, opcode: u8, label: &'static str) -> &mut Self {
+        self.fixups.push((self.code.len() + 1, label));
+        self.op_u32(opcode, 0)
+    }
+
+    pub fn label(&mut self, name: &'static str) -> &mut Self {
+        self.labels.insert(name, self.code.len() as u32);
+        self
+    }
+
+    /// Offset of `label`, which must already have been placed.
+    pub fn address(&self, label: &str) -> u32 {
+        self.labels[label]
+    }
+
+    pub fn finish(&self) -> Vec<u8> {
+        let mut code = self.code.clone();
+        for &(at, label) in &self.fixups {
+            code[at..at + 4].copy_from_slice(&self.labels[label].to_be_bytes());
+        }
+        code
+    }
+}
+
+/// A fresh, empty directory for one test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pndm-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes `graph.json` and one node file per node into a fresh directory.
+pub fn write_project(name: &str, graph: &str, nodes: &[(&str, NodeFile)]) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("graph.json"), graph).unwrap();
+    for (node, file) in nodes {
+        fs::write(dir.join(format!("{node}.k")), file.to_bytes()).unwrap();
+    }
+    dir
+}
+
+/// Writes a project whose only node, `Main`, runs `file`, and returns its
+/// path.
+pub fn single_node_project(name: &str, file: NodeFile) -> String {
+    single_node_project_with_graph(name, r#"{ "Main": [] }"#, file)
+}
+
+/// Like [`single_node_project`], with `graph` as its `graph.json`.
+pub fn single_node_project_with_graph(name: &str, graph: &str, file: NodeFile) -> String {
+    let dir = write_project(name, graph, &[("Main", file)]);
+    dir.to_string_lossy().into_owned()
+}
+
+/// A single-node project whose `Main` calls the host function `next` twice,
+/// leaving both results on the stack.
+pub fn host_counter_project(name: &str) -> String {
+    let mut asm = Asm::new();
+    asm.op_u32(0xB0, 0).op_u32(0xB0, 0);
+    let main = NodeFile {
+        code: asm.finish(),
+        strings: vec!["next".into()],
+        imports: vec![Import { name: 0, arity: 0 }],
+        ..NodeFile::default()
+    };
+    single_node_project(name, main)
+}
+
+/// Registers `next`, which returns 1, 2, 3, ... on successive calls. The
+/// returned counter is the number of calls so far.
+pub fn register_counter(vm: &mut VirtualMachine) -> Arc<AtomicU32> {
+    let calls = Arc::new(AtomicU32::new(0));
+    let counter = calls.clone();
+    vm.register_host_fn("next", 0, move |_| {
+        Ok(counter.fetch_add(1, Ordering::SeqCst) + 1)
+    })
+    .unwrap();
+    calls
+}
+
+pub const FOLD_ROUNDS: u32 = 40;
+
+/// How `Main` of a [`fold_project`] folds each reply into `acc`.
+#[derive(Clone, Copy)]
+pub enum Fold {
+    /// `acc + reply`, which does not depend on the order replies arrive in.
+    Sum,
+    /// `acc * 31 + reply`, which does.
+    Ordered,
+}
+
+/// `Main` sends 0..FOLD_ROUNDS to both workers and folds every reply into
+/// `acc` as `fold` says. `Left` squares each value and `Right` adds 1000.
+pub fn fold_project(name: &str, fold: Fold) -> String {
+    let mut main = Asm::new();
+    main.label("send")
+        .op_u32(0x22, 0)
+        .op_u32(0x10, FOLD_ROUNDS)
+        .op(0x54)
+        .jump(0x51, "receive")
+        .op_u32(0x22, 0)
+        .op_u32(0xA0, 0)
+        .op_u32(0x22, 0)
+        .op_u32(0xA0, 1)
+        .op_u32(0x22, 0)
+        .op_u32(0x10, 1)
+        .op(0x30)
+        .op_u32(0x24, 0)
+        .jump(0x5A, "send")
+        .label("receive")
+        .op_u32(0x22, 8)
+        .op_u32(0x10, 2 * FOLD_ROUNDS)
+        .op(0x54)
+        .jump(0x51, "end")
+        .op(0xA1)
+        .op_u32(0x22, 4);
+    if let Fold::Ordered = fold {
+        main.op_u32(0x10, 31).op(0x34);
+    }
+    main.op(0x30)
+        .op_u32(0x24, 4)
+        .op_u32(0x22, 8)
+        .op_u32(0x10, 1)
+        .op(0x30)
+        .op_u32(0x24, 8)
+        .jump(0x5A, "receive")
+        .label("end")
+        .op_u32(0x22, 4);
+
+    let mut left = Asm::new();
+    left.label("loop")
+        .op(0xA1)
+        .op_u32(0x24, 0)
+        .op_u32(0x22, 0)
+        .op_u32(0x22, 0)
+        .op(0x34)
+        .op_u32(0xA0, 0)
+        .jump(0x5A, "loop");
+
+    let mut right = Asm::new();
+    right
+        .label("loop")
+        .op(0xA1)
+        .op_u32(0x10, 1000)
+        .op(0x30)
+        .op_u32(0xA0, 0)
+        .jump(0x5A, "loop");
+
+    let main = NodeFile {
+        code: main.finish(),
+        memory_size: 12,
+        strings: vec!["Left".into(), "Right".into()],
+        ..NodeFile::default()
+    };
+    let worker = |asm: &Asm| NodeFile {
+        code: asm.finish(),
+        memory_size: 4,
+        strings: vec!["Main".into()],
+        ..NodeFile::default()
+    };
+
+    let graph = r#"{
+        "entry": "Main",
+        "nodes": { "Main": {}, "Left": {}, "Right": {} },
+        "edges": [
+            { "from": "Main", "to": "Left", "capacity": 2 },
+            { "from": "Main", "to": "Right", "capacity": 3 },
+            { "from": "Left", "to": "Main" },
+            { "from": "Right", "to": "Main" }
+        ]
+    }"#;
+
+    let dir = write_project(
+        name,
+        graph,
+        &[
+            ("Main", main),
+            ("Left", worker(&left)),
+            ("Right", worker(&right)),
+        ],
+    );
+    dir.to_string_lossy().into_owned()
+}
+
+pub type NodeState = (Vec<u32>, Vec<u8>, u64);
+
+/// Stack, memory and step count of each node of a [`fold_project`].
+pub fn node_states(vm: &VirtualMachine) -> Vec<NodeState> {
+    ["Main", "Left", "Right"]
+        .iter()
+        .map(|name| {
+            let node = vm.node(name).unwrap();
+            (
+                node.stack().iter().copied().collect(),
+                node.memory().to_vec(),
+                node.steps(),
+            )
+        })
+        .collect()
+}
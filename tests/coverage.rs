@@ -0,0 +1,151 @@
+mod common;
+
+use common::{single_node_project, Asm};
+use pndm::{
+    coverage::Branch,
+    node_file::{DebugInfo, LineEntry, NodeFile},
+    vm::VirtualMachine,
+};
+
+/// Counts `i` from 0 to 3, checking on each pass for an `i > 100` that never
+/// happens. Each label starts the source line of the same number.
+fn coverage_project(name: &str) -> (String, Asm) {
+    let mut asm = Asm::new();
+    asm.label("2")
+        .op_u32(0x22, 0)
+        .op_u32(0x10, 3)
+        .op(0x54)
+        .jump(0x51, "7")
+        .label("3")
+        .op_u32(0x22, 0)
+        .op_u32(0x10, 1)
+        .op(0x30)
+        .op_u32(0x24, 0)
+        .label("4")
+        .op_u32(0x22, 0)
+        .op_u32(0x10, 100)
+        .op(0x56)
+        .jump(0x50, "6")
+        .label("5")
+        .jump(0x5A, "2")
+        .label("6")
+        .op_u32(0x10, 7)
+        .op_u32(0x24, 4)
+        .label("7")
+        .op(0x26);
+
+    let mut lines = vec![LineEntry { pc: 0, line: 1 }];
+    for line in 2..=7 {
+        lines.push(LineEntry {
+            pc: asm.address(&line.to_string()),
+            line,
+        });
+    }
+    let main = NodeFile {
+        code: asm.finish(),
+        memory_size: 8,
+        strings: vec!["src/main.krm".into()],
+        debug: Some(DebugInfo { file: 0, lines }),
+        ..NodeFile::default()
+    };
+
+    (single_node_project(name, main), asm)
+}
+
+#[test]
+fn coverage_counts_instructions_and_branches() {
+    let (path, asm) = coverage_project("coverage-counts");
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.collect_coverage();
+    vm.execute().unwrap();
+
+    let coverage = vm.node("Main").unwrap().coverage().unwrap();
+    assert_eq!(coverage.instructions(), (15, 17));
+    let dead = asm.address("6") as usize;
+    assert_eq!(coverage.uncovered(), vec![(dead, dead + 5)]);
+
+    let loop_test = asm.address("3") as usize - 5;
+    let never = asm.address("5") as usize - 5;
+    assert_eq!(coverage.hits(loop_test), 4);
+    assert_eq!(
+        coverage.branch(loop_test),
+        Some(Branch {
+            taken: 1,
+            not_taken: 3
+        })
+    );
+    assert_eq!(
+        coverage.branch(never),
+        Some(Branch {
+            taken: 0,
+            not_taken: 3
+        })
+    );
+    assert_eq!(coverage.branch_outcomes(), (3, 4));
+}
+
+#[test]
+fn lcov_reports_lines_and_branches() {
+    let (path, asm) = coverage_project("coverage-lcov");
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.collect_coverage();
+    vm.execute().unwrap();
+
+    let lcov = vm.node("Main").unwrap().coverage().unwrap().lcov().unwrap();
+    let loop_test = asm.address("3") - 5;
+    let never = asm.address("5") - 5;
+    let expected = format!(
+        "TN:\n\
+         SF:src/main.krm\n\
+         BRDA:2,{loop_test},0,1\n\
+         BRDA:2,{loop_test},1,3\n\
+         BRDA:4,{never},0,0\n\
+         BRDA:4,{never},1,3\n\
+         BRF:4\n\
+         BRH:3\n\
+         DA:1,1\n\
+         DA:2,4\n\
+         DA:3,3\n\
+         DA:4,3\n\
+         DA:5,3\n\
+         DA:6,0\n\
+         DA:7,1\n\
+         LF:7\n\
+         LH:6\n\
+         end_of_record\n"
+    );
+    assert_eq!(lcov, expected);
+}
+
+#[test]
+fn branches_to_the_next_instruction_count_their_condition() {
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 1)
+        .jump(0x50, "first")
+        .label("first")
+        .op_u32(0x10, 0)
+        .jump(0x50, "second")
+        .label("second");
+    let main = NodeFile {
+        code: asm.finish(),
+        ..NodeFile::default()
+    };
+
+    let mut vm = VirtualMachine::new(&single_node_project("coverage-next", main)).unwrap();
+    vm.collect_coverage();
+    vm.execute().unwrap();
+
+    let coverage = vm.node("Main").unwrap().coverage().unwrap();
+    let taken = Branch {
+        taken: 1,
+        not_taken: 0,
+    };
+    let not_taken = Branch {
+        taken: 0,
+        not_taken: 1,
+    };
+    let first = asm.address("first") as usize - 5;
+    let second = asm.address("second") as usize - 5;
+    assert_eq!(coverage.branch(first), Some(taken));
+    assert_eq!(coverage.branch(second), Some(not_taken));
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use common::{fold_project, host_counter_project, node_states, register_counter, Fold};
+use pndm::{
+    debugger::{Debugger, StopReason},
+    vm::VirtualMachine,
+};
+
+/// Offsets in `Main` of a [`fold_project`]: its receive, and its store to
+/// `acc` at address 4.
+const RECEIVE: usize = 74;
+const STORE_ACC: usize = 87;
+
+fn debugger(path: &str, interval: u64) -> Debugger {
+    let mut vm = VirtualMachine::new(path).unwrap();
+    vm.set_slice(7);
+    Debugger::new(vm, interval).unwrap()
+}
+
+#[test]
+fn stepping_back_restores_earlier_state() {
+    let path = fold_project("debugger-step-back", Fold::Ordered);
+    let mut debugger = debugger(&path, 100);
+
+    debugger.step(250).unwrap();
+    let earlier = node_states(debugger.vm());
+
+    assert_eq!(debugger.step(1000).unwrap(), StopReason::Stepped);
+    assert_eq!(debugger.step_back(1000).unwrap(), StopReason::Stepped);
+    assert_eq!(debugger.time(), 250);
+    assert_eq!(node_states(debugger.vm()), earlier);
+
+    assert_eq!(debugger.step_back(1000).unwrap(), StopReason::Origin);
+    assert_eq!(debugger.time(), 0);
+}
+
+#[test]
+fn debugged_run_matches_normal_run() {
+    let path = fold_project("debugger-matches-run", Fold::Ordered);
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.set_slice(7);
+    vm.execute().unwrap();
+
+    let mut debugger = debugger(&path, 64);
+    assert_eq!(debugger.continue_forward().unwrap(), StopReason::Finished);
+    assert_eq!(node_states(debugger.vm()), node_states(&vm));
+
+    // Going back and running forward again reaches the same end.
+    debugger.goto(333).unwrap();
+    assert_eq!(debugger.continue_forward().unwrap(), StopReason::Finished);
+    assert_eq!(node_states(debugger.vm()), node_states(&vm));
+}
+
+#[test]
+fn last_write_finds_the_store() {
+    let path = fold_project("debugger-last-write", Fold::Ordered);
+    let mut debugger = debugger(&path, 64);
+    debugger.continue_forward().unwrap();
+
+    let end = debugger.time();
+    let acc = debugger.vm().node("Main").unwrap().memory()[4..8].to_vec();
+
+    let write = debugger.last_write("Main", 6).unwrap().unwrap();
+    assert_eq!(write.pc, STORE_ACC);
+    assert_eq!(debugger.time(), end);
+
+    // Executing the write gives acc its final value.
+    debugger.goto(write.time).unwrap();
+    assert_eq!(debugger.vm().node("Main").unwrap().pc(), STORE_ACC);
+    debugger.step(1).unwrap();
+    assert_eq!(debugger.vm().node("Main").unwrap().memory()[4..8], acc);
+
+    assert_eq!(debugger.last_write("Left", 100).unwrap(), None);
+}
+
+#[test]
+fn reverse_continue_stops_at_earlier_breakpoints() {
+    let path = fold_project("debugger-reverse-continue", Fold::Ordered);
+    let mut debugger = debugger(&path, 64);
+    debugger.add_breakpoint("Main", RECEIVE).unwrap();
+
+    let mut hits = vec![];
+    while let StopReason::Breakpoint { .. } = debugger.continue_forward().unwrap() {
+        assert_eq!(debugger.vm().node("Main").unwrap().pc(), RECEIVE);
+        hits.push(debugger.time());
+    }
+    assert_eq!(hits.len(), 80);
+
+    for &hit in hits.iter().rev() {
+        assert!(matches!(
+            debugger.reverse_continue().unwrap(),
+            StopReason::Breakpoint { .. }
+        ));
+        assert_eq!(debugger.time(), hit);
+    }
+    assert_eq!(debugger.reverse_continue().unwrap(), StopReason::Origin);
+}
+
+#[test]
+fn reverse_continue_stops_before_watched_write() {
+    let path = fold_project("debugger-reverse-watch", Fold::Ordered);
+    let mut debugger = debugger(&path, 64);
+    debugger.continue_forward().unwrap();
+    let last = debugger.last_write("Main", 4).unwrap().unwrap();
+
+    debugger.add_watchpoint("Main", 4, 4).unwrap();
+    assert_eq!(
+        debugger.reverse_continue().unwrap(),
+        StopReason::Watchpoint(last)
+    );
+    assert_eq!(debugger.time(), last.time);
+
+    // Going forward again stops just after the same write.
+    assert_eq!(
+        debugger.continue_forward().unwrap(),
+        StopReason::Watchpoint(last)
+    );
+    assert_eq!(debugger.time(), last.time + 1);
+}
+
+#[test]
+fn re_executed_host_calls_return_their_first_results() {
+    let path = host_counter_project("debugger-host");
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    let calls = register_counter(&mut vm);
+    let mut debugger = Debugger::new(vm, 1).unwrap();
+
+    let stack = |debugger: &Debugger| -> Vec<u32> {
+        let main = debugger.vm().node("Main").unwrap();
+        main.stack().iter().copied().collect()
+    };
+    assert_eq!(debugger.step(3).unwrap(), StopReason::Stepped);
+    assert_eq!(stack(&debugger), vec![1, 2]);
+
+    debugger.step_back(2).unwrap();
+    assert_eq!(debugger.step(2).unwrap(), StopReason::Stepped);
+    assert_eq!(stack(&debugger), vec![1, 2]);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
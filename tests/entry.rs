@@ -0,0 +1,107 @@
+mod common;
+
+use common::{write_project, Asm};
+use pndm::{
+    error::Error,
+    graph::Limits,
+    node_file::{NodeFile, Symbol, SymbolKind},
+    vm::VirtualMachine,
+};
+
+/// Exports `double(x)` and `spin()`, which never returns, and has a private
+/// `hidden()` that is never called.
+fn entry_project(name: &str, graph: &str) -> String {
+    let mut asm = Asm::new();
+    asm.label("double")
+        .op_u32(0x10, 2)
+        .op(0x34)
+        .op(0x5B)
+        .label("hidden")
+        .op_u32(0x10, 1)
+        .op(0x5B)
+        .label("spin")
+        .jump(0x5A, "spin");
+
+    let function = |name: u32, exported: bool, arity: u8, address: u32| Symbol {
+        name,
+        kind: SymbolKind::Function,
+        exported,
+        arity,
+        address,
+        size: 0,
+    };
+    let main = NodeFile {
+        symbols: vec![
+            function(0, true, 1, asm.address("double")),
+            function(1, false, 0, asm.address("hidden")),
+            function(2, true, 0, asm.address("spin")),
+        ],
+        code: asm.finish(),
+        strings: vec!["double".into(), "hidden".into(), "spin".into()],
+        ..NodeFile::default()
+    };
+
+    let dir = write_project(name, graph, &[("Main", main)]);
+    dir.to_string_lossy().into_owned()
+}
+
+#[test]
+fn exported_functions_are_called_by_name() {
+    let mut vm = VirtualMachine::new(&entry_project("entry-call", r#"{ "Main": [] }"#)).unwrap();
+    assert_eq!(vm.call("Main", "double", &[21]).unwrap(), Some(42));
+
+    let err = vm.call("Main", "hidden", &[]).unwrap_err();
+    assert!(matches!(err, Error::UnknownExport { function, .. } if function == "hidden"));
+    let err = vm.call("Main", "double", &[1, 2]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::WrongArgumentCount {
+            expected: 1,
+            found: 2,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn graph_json_starts_nodes_in_their_entry_function() {
+    let graph = r#"{ "nodes": { "Main": { "entry": "double", "params": [5] } } }"#;
+    let mut vm = VirtualMachine::new(&entry_project("entry-config", graph)).unwrap();
+    vm.execute().unwrap();
+    let stack: Vec<u32> = vm.node("Main").unwrap().stack().iter().copied().collect();
+    assert_eq!(stack, vec![10]);
+
+    let graph = r#"{ "nodes": { "Main": { "entry": "missing" } } }"#;
+    let err = VirtualMachine::new(&entry_project("entry-missing", graph)).unwrap_err();
+    assert!(matches!(err, Error::Graph { message, .. } if message.starts_with("nodes.Main.entry")));
+}
+
+#[test]
+fn graph_limits_apply_to_calls() {
+    let path = entry_project("entry-limits", r#"{ "Main": [] }"#);
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    let limits = Limits {
+        instructions: Some(1000),
+        ..Limits::default()
+    };
+    vm.set_limits(limits, Limits::default()).unwrap();
+    let err = vm.call("Main", "spin", &[]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::InstructionLimit {
+            node: None,
+            limit: 1000
+        }
+    ));
+
+    let graph = r#"{ "nodes": { "Main": {} }, "limits": { "time_ms": 20 } }"#;
+    let mut vm = VirtualMachine::new(&entry_project("entry-timeout", graph)).unwrap();
+    let err = vm.call("Main", "spin", &[]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::TimeLimit {
+            node: None,
+            limit_ms: 20
+        }
+    ));
+}
@@ -0,0 +1,237 @@
+mod common;
+
+use std::fs;
+
+use common::{write_project, Asm};
+use pndm::{
+    debugger::{Debugger, StopReason},
+    error::{Error, Trap},
+    files::{FILE_BAD_HANDLE, FILE_DENIED, FILE_OK, MAX_OPEN_FILES, MODE_READ, MODE_WRITE},
+    node_file::NodeFile,
+    vm::VirtualMachine,
+};
+
+const PATHS: [(u32, &str); 4] = [
+    (0, "data/in.txt"),
+    (16, "data/out.txt"),
+    (32, "secret.txt"),
+    (48, "data/../secret.txt"),
+];
+const BUFFER: u32 = 80;
+
+/// Copies the first five bytes of `data/in.txt` to `data/out.txt`, tries to
+/// open `secret.txt` directly and through `data/..`, and reads from a handle
+/// that was never opened. Handles are kept at 100 and 108.
+fn files_file() -> NodeFile {
+    let open = |asm: &mut Asm, (addr, path): (u32, &str), mode: u32| {
+        asm.op_u32(0x10, addr)
+            .op_u32(0x10, path.len() as u32)
+            .op_u32(0x10, mode)
+            .op(0xC0);
+    };
+
+    let mut asm = Asm::new();
+    open(&mut asm, PATHS[0], MODE_READ);
+    asm.op_u32(0x24, 96)
+        .op_u32(0x24, 100)
+        .op_u32(0x22, 100)
+        .op_u32(0x10, BUFFER)
+        .op_u32(0x10, 16)
+        .op(0xC1);
+    open(&mut asm, PATHS[1], MODE_WRITE);
+    asm.op_u32(0x24, 104)
+        .op_u32(0x24, 108)
+        .op_u32(0x22, 108)
+        .op_u32(0x10, BUFFER)
+        .op_u32(0x10, 5)
+        .op(0xC2)
+        .op_u32(0x22, 108)
+        .op(0xC3);
+    open(&mut asm, PATHS[2], MODE_READ);
+    open(&mut asm, PATHS[3], MODE_READ);
+    asm.op_u32(0x10, 99)
+        .op_u32(0x10, BUFFER)
+        .op_u32(0x10, 1)
+        .op(0xC1)
+        .op_u32(0x22, 100)
+        .op(0xC3);
+
+    let mut data = vec![0; BUFFER as usize];
+    for (addr, path) in PATHS {
+        data[addr as usize..addr as usize + path.len()].copy_from_slice(path.as_bytes());
+    }
+    NodeFile {
+        code: asm.finish(),
+        memory_size: BUFFER + 32,
+        data,
+        ..NodeFile::default()
+    }
+}
+
+fn files_project(name: &str, graph: &str) -> String {
+    let dir = write_project(name, graph, &[("Main", files_file())]);
+    fs::create_dir(dir.join("data")).unwrap();
+    fs::write(dir.join("data/in.txt"), "hello, world").unwrap();
+    fs::write(dir.join("secret.txt"), "secret").unwrap();
+    dir.to_string_lossy().into_owned()
+}
+
+fn stack(vm: &VirtualMachine) -> Vec<u32> {
+    vm.node("Main").unwrap().stack().iter().copied().collect()
+}
+
+#[test]
+fn files_are_confined_to_granted_directories() {
+    let path = files_project(
+        "files-granted",
+        r#"{ "nodes": { "Main": {} }, "files": ["data"] }"#,
+    );
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.execute().unwrap();
+
+    assert_eq!(
+        stack(&vm),
+        vec![
+            12,
+            FILE_OK,
+            FILE_OK,
+            FILE_OK,
+            0,
+            FILE_DENIED,
+            0,
+            FILE_DENIED,
+            0,
+            FILE_BAD_HANDLE,
+            FILE_OK,
+        ]
+    );
+    let memory = vm.node("Main").unwrap().memory();
+    assert_eq!(memory[96..100], FILE_OK.to_be_bytes());
+    assert_eq!(
+        &memory[BUFFER as usize..BUFFER as usize + 12],
+        b"hello, world"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{path}/data/out.txt")).unwrap(),
+        "hello"
+    );
+}
+
+#[test]
+fn no_directories_are_granted_by_default() {
+    let path = files_project("files-default", r#"{ "Main": [] }"#);
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.execute().unwrap();
+
+    let stack = stack(&vm);
+    assert_eq!(stack[..2], [0, FILE_BAD_HANDLE]);
+    assert!(!fs::exists(format!("{path}/data/out.txt")).unwrap());
+    let memory = vm.node("Main").unwrap().memory();
+    assert_eq!(memory[96..100], FILE_DENIED.to_be_bytes());
+
+    let path = files_project(
+        "files-missing",
+        r#"{ "nodes": { "Main": {} }, "files": ["out"] }"#,
+    );
+    let err = VirtualMachine::new(&path).unwrap_err();
+    assert!(matches!(&err, Error::Graph { message, .. } if message.starts_with("files[0]")));
+}
+
+#[cfg(unix)]
+#[test]
+fn dangling_links_are_not_followed_out_of_granted_directories() {
+    let path = files_project(
+        "files-dangling",
+        r#"{ "nodes": { "Main": {} }, "files": ["data"] }"#,
+    );
+    std::os::unix::fs::symlink("../escaped.txt", format!("{path}/data/out.txt")).unwrap();
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.execute().unwrap();
+
+    let memory = vm.node("Main").unwrap().memory();
+    assert_eq!(memory[104..108], FILE_DENIED.to_be_bytes());
+    assert!(!fs::exists(format!("{path}/escaped.txt")).unwrap());
+}
+
+/// The stack and memory of `Main`.
+fn state(vm: &VirtualMachine) -> (Vec<u32>, Vec<u8>) {
+    (stack(vm), vm.node("Main").unwrap().memory().to_vec())
+}
+
+#[test]
+fn replays_repeat_file_operations_without_touching_the_files() {
+    let path = files_project(
+        "files-replay",
+        r#"{ "nodes": { "Main": {} }, "files": ["data"] }"#,
+    );
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.record();
+    vm.execute().unwrap();
+    let log = vm.recording().unwrap();
+
+    fs::write(format!("{path}/data/in.txt"), "changed").unwrap();
+    fs::remove_file(format!("{path}/data/out.txt")).unwrap();
+    let mut replayed = VirtualMachine::new(&path).unwrap();
+    replayed.replay(&log).unwrap();
+    assert_eq!(state(&replayed), state(&vm));
+    assert!(!fs::exists(format!("{path}/data/out.txt")).unwrap());
+}
+
+#[test]
+fn the_debugger_repeats_file_operations_without_touching_the_files() {
+    let path = files_project(
+        "files-debugger",
+        r#"{ "nodes": { "Main": {} }, "files": ["data"] }"#,
+    );
+    let vm = VirtualMachine::new(&path).unwrap();
+    let mut debugger = Debugger::new(vm, 8).unwrap();
+    assert_eq!(debugger.continue_forward().unwrap(), StopReason::Finished);
+    let expected = state(debugger.vm());
+
+    fs::write(format!("{path}/data/in.txt"), "changed").unwrap();
+    fs::remove_file(format!("{path}/data/out.txt")).unwrap();
+    debugger.goto(0).unwrap();
+    assert_eq!(debugger.continue_forward().unwrap(), StopReason::Finished);
+    assert_eq!(state(debugger.vm()), expected);
+    assert!(!fs::exists(format!("{path}/data/out.txt")).unwrap());
+}
+
+#[test]
+fn opening_too_many_files_traps() {
+    let (addr, path) = PATHS[0];
+    let mut asm = Asm::new();
+    asm.label("open")
+        .op_u32(0x10, addr)
+        .op_u32(0x10, path.len() as u32)
+        .op_u32(0x10, MODE_READ)
+        .op(0xC0)
+        .op(0x12)
+        .op(0x12)
+        .jump(0x5A, "open");
+    let main = NodeFile {
+        code: asm.finish(),
+        memory_size: 16,
+        data: path.as_bytes().to_vec(),
+        ..NodeFile::default()
+    };
+
+    let graph = r#"{ "nodes": { "Main": {} }, "files": ["data"] }"#;
+    let dir = write_project("files-too-many", graph, &[("Main", main)]);
+    fs::create_dir(dir.join("data")).unwrap();
+    fs::write(dir.join("data/in.txt"), "hello, world").unwrap();
+
+    let mut vm = VirtualMachine::new(&dir.to_string_lossy()).unwrap();
+    let err = vm.execute().unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Trap {
+                trap: Trap::TooManyOpenFiles {
+                    limit: MAX_OPEN_FILES
+                },
+                ..
+            }
+        ),
+        "{err}"
+    );
+}
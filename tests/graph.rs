@@ -0,0 +1,111 @@
+mod common;
+
+use common::write_project;
+use pndm::{
+    error::Error,
+    graph::{GraphConfig, GraphError},
+    node_file::NodeFile,
+    vm::VirtualMachine,
+};
+
+fn two_node_project(name: &str, graph: &str) -> String {
+    let node = || NodeFile {
+        code: vec![0x26],
+        ..NodeFile::default()
+    };
+    let dir = write_project(name, graph, &[("A", node()), ("B", node())]);
+    dir.to_string_lossy().into_owned()
+}
+
+#[test]
+fn entry_nodes_come_from_graph_json_or_an_override() {
+    let mut vm =
+        VirtualMachine::new(&two_node_project("entry-none", r#"{ "A": [], "B": [] }"#)).unwrap();
+    let err = vm.start_nodes().unwrap_err();
+    assert!(matches!(err, Error::AmbiguousEntry { nodes } if nodes == ["A", "B"]));
+
+    vm.set_entry_node("B").unwrap();
+    assert_eq!(vm.start_nodes().unwrap(), vec![1]);
+    assert!(matches!(
+        vm.set_entry_node("C").unwrap_err(),
+        Error::UnknownNode { node } if node == "C"
+    ));
+
+    let graph = r#"{ "entry": ["B", "A"], "nodes": { "A": {}, "B": {} } }"#;
+    let vm = VirtualMachine::new(&two_node_project("entry-many", graph)).unwrap();
+    assert_eq!(vm.start_nodes().unwrap(), vec![1, 0]);
+}
+
+fn graph_error(json: &str) -> GraphError {
+    GraphConfig::parse(json).unwrap_err()
+}
+
+#[test]
+fn graph_json_forms_are_equivalent() {
+    let full = r#"{
+        "nodes": { "A": {}, "B": { "bytecode": "b.k" } },
+        "edges": [{ "from": "A", "to": "B", "direction": "both", "capacity": 2 }]
+    }"#;
+    let config = GraphConfig::parse(full).unwrap();
+    assert_eq!(config.bytecode_path("A"), "A.k");
+    assert_eq!(config.bytecode_path("B"), "b.k");
+    assert_eq!(config.edges[0].links(), [("A", "B"), ("B", "A")]);
+
+    let nested = GraphConfig::parse(r#"{ "nodes": { "A": ["B"], "B": {} } }"#).unwrap();
+    let adjacency = GraphConfig::parse(r#"{ "A": ["B"], "B": [] }"#).unwrap();
+    assert_eq!(nested, adjacency);
+}
+
+#[test]
+fn invalid_graphs_name_the_offending_key() {
+    let err = graph_error(r#"{ "A": ["C"], "B": [] }"#);
+    assert_eq!(
+        (err.key.as_str(), err.message.as_str()),
+        ("A[0]", "unknown node C")
+    );
+
+    let err = graph_error(r#"{ "nodes": { "A": {} }, "edges": [{ "from": "A", "to": "A" }] }"#);
+    assert_eq!(
+        (err.key.as_str(), err.message.as_str()),
+        ("edges[0]", "self-loop on A")
+    );
+
+    let err = graph_error(
+        r#"{ "nodes": { "A": ["B"], "B": {} }, "edges": [{ "from": "B", "to": "A", "direction": "backward" }] }"#,
+    );
+    assert_eq!(err.key, "edges[0]");
+    assert_eq!(
+        err.message,
+        "duplicate edge A -> B, already declared by nodes.A[0]"
+    );
+
+    let err = graph_error(
+        r#"{ "nodes": { "A": {}, "B": {} }, "edges": [{ "from": "A", "to": "B", "capacity": 0 }] }"#,
+    );
+    assert_eq!(err.message, "capacity must be at least 1");
+
+    let err = graph_error(
+        r#"{ "nodes": { "A": {}, "B": {}, "C": {} }, "edges": [{ "from": "A", "to": "B", "name": "C" }] }"#,
+    );
+    assert_eq!(err.message, "edge name C is the name of another node");
+    GraphConfig::parse(
+        r#"{ "nodes": { "A": {}, "B": {} }, "edges": [{ "from": "A", "to": "B", "name": "B" }] }"#,
+    )
+    .unwrap();
+
+    let err = graph_error(r#"{ "entry": "C", "nodes": { "A": {} } }"#);
+    assert_eq!(err.key, "entry[0]");
+    assert!(graph_error(r#"{ "nodes": { "A": { "ports": 1 } } }"#)
+        .message
+        .contains("unknown field"));
+}
+
+#[test]
+fn missing_node_files_are_reported_before_loading() {
+    let dir = write_project("graph-missing", r#"{ "A": ["B"], "B": [] }"#, &[]);
+    let err = VirtualMachine::new(&dir.to_string_lossy()).unwrap_err();
+    assert!(
+        matches!(&err, Error::Graph { message, .. } if message.starts_with("nodes.A.bytecode")),
+        "{err}"
+    );
+}
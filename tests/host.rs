@@ -0,0 +1,126 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{single_node_project, Asm};
+use pndm::{
+    error::{Error, Trap},
+    node_file::{Import, LoadError, NodeFile},
+    vm::VirtualMachine,
+};
+
+/// Computes `sub(10, 3)` with the host, stores it at address 0 and passes it
+/// on to `note`.
+fn host_file() -> NodeFile {
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 10)
+        .op_u32(0x10, 3)
+        .op_u32(0xB0, 0)
+        .op_u32(0x24, 0)
+        .op_u32(0x22, 0)
+        .op_u32(0xB0, 1)
+        .op(0x12);
+
+    NodeFile {
+        code: asm.finish(),
+        memory_size: 4,
+        strings: vec!["sub".into(), "note".into()],
+        imports: vec![Import { name: 0, arity: 2 }, Import { name: 1, arity: 1 }],
+        ..NodeFile::default()
+    }
+}
+
+fn host_project(name: &str) -> VirtualMachine {
+    VirtualMachine::new(&single_node_project(name, host_file())).unwrap()
+}
+
+fn register_sub(vm: &mut VirtualMachine) {
+    vm.register_host_fn("sub", 2, |args| Ok(args[0].wrapping_sub(args[1])))
+        .unwrap();
+}
+
+#[test]
+fn host_functions_get_arguments_in_push_order() {
+    let mut vm = host_project("host-call");
+    register_sub(&mut vm);
+    let notes = Arc::new(Mutex::new(vec![]));
+    let seen = notes.clone();
+    vm.register_host_fn("note", 1, move |args| {
+        seen.lock().unwrap().push(args[0]);
+        Ok(0)
+    })
+    .unwrap();
+
+    vm.execute().unwrap();
+
+    let main = vm.node("Main").unwrap();
+    assert_eq!(main.memory()[..4], 7u32.to_be_bytes());
+    assert_eq!(*notes.lock().unwrap(), vec![7]);
+}
+
+#[test]
+fn missing_import_is_reported_before_running() {
+    let mut vm = host_project("host-missing");
+    register_sub(&mut vm);
+
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(
+        &err,
+        Error::MissingImport { node, function } if node == "Main" && function == "note"
+    ));
+    assert_eq!(vm.steps(), 0);
+}
+
+#[test]
+fn registering_with_the_wrong_arity_fails() {
+    let mut vm = host_project("host-arity");
+    let err = vm.register_host_fn("sub", 3, |_| Ok(0)).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::HostArity {
+            imported: 2,
+            registered: 3,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn host_errors_trap() {
+    let mut vm = host_project("host-error");
+    register_sub(&mut vm);
+    vm.register_host_fn("note", 1, |_| Err("no room".to_string()))
+        .unwrap();
+
+    let err = vm.execute().unwrap_err();
+    let Error::Trap { trap, .. } = err else {
+        panic!("expected a trap, got {err}");
+    };
+    assert_eq!(
+        trap,
+        Trap::HostError {
+            function: "note".into(),
+            message: "no room".into(),
+        }
+    );
+}
+
+#[test]
+fn imports_round_trip_and_are_checked() {
+    let file = host_file();
+    let parsed = NodeFile::parse(&file.to_bytes()).unwrap();
+    assert_eq!(parsed.imports, file.imports);
+
+    let mut missing = file;
+    missing.imports.pop();
+    assert_eq!(
+        NodeFile::parse(&missing.to_bytes()).unwrap_err(),
+        LoadError::InvalidImportIndex { index: 1 }
+    );
+
+    // Legacy files have no imports to call.
+    assert_eq!(
+        NodeFile::parse(&[0x26, 0xB0, 0, 0, 0, 0]).unwrap_err(),
+        LoadError::InvalidImportIndex { index: 0 }
+    );
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::{single_node_project, temp_dir, Asm};
+use pndm::{
+    debugger::Debugger,
+    error::{Error, Trap},
+    input::{Input, READ_EOF, READ_INVALID, READ_OK},
+    node_file::NodeFile,
+    snapshot::GraphCheckpoint,
+    vm::VirtualMachine,
+};
+
+const INPUT: &str = "-42 x7 2.5\nhello world\n";
+
+/// Reads an int, a token that is not one, a float, the newline after it, the
+/// first five bytes of the next line into address 0, and then finds the end
+/// of the input. Everything read stays on the stack.
+fn input_project(name: &str) -> String {
+    let mut asm = Asm::new();
+    asm.op(0x94)
+        .op(0x94)
+        .op(0x95)
+        .op(0x96)
+        .op_u32(0x10, 0)
+        .op_u32(0x10, 5)
+        .op(0x97)
+        .op(0x94);
+
+    let main = NodeFile {
+        code: asm.finish(),
+        memory_size: 8,
+        ..NodeFile::default()
+    };
+    single_node_project(name, main)
+}
+
+fn run_state(vm: &VirtualMachine) -> (Vec<u32>, Vec<u8>) {
+    let main = vm.node("Main").unwrap();
+    (
+        main.stack().iter().copied().collect(),
+        main.memory().to_vec(),
+    )
+}
+
+#[test]
+fn input_opcodes_report_values_and_status() {
+    let mut vm = VirtualMachine::new(&input_project("input-read")).unwrap();
+    vm.set_input(Cursor::new(INPUT));
+    vm.execute().unwrap();
+
+    let (stack, memory) = run_state(&vm);
+    assert_eq!(
+        stack,
+        vec![
+            -42i32 as u32,
+            READ_OK,
+            0,
+            READ_INVALID,
+            2.5f32.to_bits(),
+            READ_OK,
+            b'\n' as u32,
+            READ_OK,
+            5,
+            READ_OK,
+            0,
+            READ_EOF,
+        ]
+    );
+    assert_eq!(&memory[..5], b"hello");
+}
+
+#[test]
+fn replay_and_restore_see_the_same_input() {
+    let path = input_project("input-rerun");
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.set_input(Cursor::new(INPUT));
+    vm.record();
+    vm.execute().unwrap();
+    let expected = run_state(&vm);
+    let log = vm.recording().unwrap();
+    assert_eq!(log.input, INPUT.as_bytes());
+
+    let mut replayed = VirtualMachine::new(&path).unwrap();
+    replayed.replay(&log).unwrap();
+    assert_eq!(run_state(&replayed), expected);
+
+    let checkpoint = temp_dir("input-checkpoint").join("checkpoint.json");
+    let checkpoint = checkpoint.to_string_lossy();
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.set_input(Cursor::new(INPUT));
+    vm.execute_with_checkpoint(3, &checkpoint).unwrap();
+
+    let mut resumed = VirtualMachine::new(&path).unwrap();
+    resumed.set_input(Cursor::new(INPUT));
+    resumed
+        .restore(GraphCheckpoint::read(&checkpoint).unwrap())
+        .unwrap();
+    resumed.execute().unwrap();
+    assert_eq!(run_state(&resumed), expected);
+}
+
+#[test]
+fn debugger_rewinds_input() {
+    let path = input_project("input-debug");
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.set_input(Cursor::new(INPUT));
+    vm.execute().unwrap();
+    let expected = run_state(&vm);
+
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.set_input(Cursor::new(INPUT));
+    let mut debugger = Debugger::new(vm, 2).unwrap();
+    debugger.continue_forward().unwrap();
+    debugger.goto(3).unwrap();
+    debugger.continue_forward().unwrap();
+    assert_eq!(run_state(debugger.vm()), expected);
+}
+
+#[test]
+fn reading_a_line_into_a_bad_buffer_consumes_nothing() {
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 6).op_u32(0x10, 5).op(0x97);
+    let main = NodeFile {
+        code: asm.finish(),
+        memory_size: 8,
+        ..NodeFile::default()
+    };
+    let mut vm = VirtualMachine::new(&single_node_project("input-bad-buffer", main)).unwrap();
+    vm.set_input(Cursor::new(INPUT));
+    vm.record();
+
+    let err = vm.execute().unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Trap {
+                trap: Trap::MemoryOutOfBounds { addr: 6, size: 8 },
+                ..
+            }
+        ),
+        "{err}"
+    );
+    assert_eq!(vm.recording().unwrap().input, b"");
+    assert_eq!(vm.node("Main").unwrap().memory(), [0; 8]);
+}
+
+#[test]
+fn consumed_input_is_kept_only_from_where_it_may_be_rewound_to() {
+    let input = Input::new(Cursor::new(INPUT));
+    input.read_line().unwrap();
+    input.keep_from(input.position());
+    assert_eq!(input.read_line().unwrap().unwrap(), b"hello world");
+    assert_eq!(input.consumed(), b"hello world\n");
+
+    // The first line is gone, so rewinding stops after it.
+    input.seek(0);
+    assert_eq!(input.position(), 11);
+    assert_eq!(input.read_line().unwrap().unwrap(), b"hello world");
+
+    let input = Input::new(Cursor::new(INPUT));
+    input.read_line().unwrap();
+    input.read_line().unwrap();
+    assert_eq!(input.consumed(), b"");
+}
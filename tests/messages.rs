@@ -0,0 +1,70 @@
+mod common;
+
+use common::{write_project, Asm};
+use pndm::{
+    error::{Error, Trap},
+    node_file::{LoadError, NodeFile},
+    vm::VirtualMachine,
+};
+
+/// Sends 7 to `target`, then halts.
+fn sender(target: &str) -> NodeFile {
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 7).op_u32(0xA0, 0);
+    NodeFile {
+        code: asm.finish(),
+        strings: vec![target.into()],
+        ..NodeFile::default()
+    }
+}
+
+fn send_error(name: &str, target: &str) -> Error {
+    let dir = write_project(
+        name,
+        r#"{ "entry": "A", "nodes": { "A": ["B"], "B": {} } }"#,
+        &[("A", sender(target)), ("B", sender("A"))],
+    );
+    let mut vm = VirtualMachine::new(&dir.to_string_lossy()).unwrap();
+    vm.execute().unwrap_err()
+}
+
+#[test]
+fn sends_only_reach_neighbors() {
+    for (name, target) in [("send-unknown", "C"), ("send-self", "A")] {
+        let err = send_error(name, target);
+        assert!(
+            matches!(
+                &err,
+                Error::Trap { node, pc: 6, trap: Trap::NotANeighbor { target: t } }
+                    if node == "A" && t == target
+            ),
+            "{err}"
+        );
+    }
+
+    let dir = write_project(
+        "send-backward",
+        r#"{ "entry": "B", "nodes": { "A": ["B"], "B": {} } }"#,
+        &[("A", sender("B")), ("B", sender("A"))],
+    );
+    let mut vm = VirtualMachine::new(&dir.to_string_lossy()).unwrap();
+    let err = vm.execute().unwrap_err();
+    assert!(
+        matches!(
+            &err,
+            Error::Trap {
+                trap: Trap::NotANeighbor { .. },
+                ..
+            }
+        ),
+        "{err}"
+    );
+}
+
+#[test]
+fn legacy_files_cannot_send() {
+    assert_eq!(
+        NodeFile::parse(&[0x26, 0xA0, 0, 0, 0, 0]).unwrap_err(),
+        LoadError::InvalidStringIndex { index: 0 }
+    );
+}
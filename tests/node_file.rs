@@ -0,0 +1,138 @@
+mod common;
+
+use std::fs;
+
+use common::{temp_dir, Asm};
+use pndm::{
+    error::Error,
+    graph::Limits,
+    node_file::{DebugInfo, LineEntry, LoadError, NodeFile, Symbol, SymbolKind, MAGIC, VERSION},
+    vm::{NodeMachine, VirtualMachine},
+};
+
+fn sample_file() -> NodeFile {
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 7).op(0x12).op(0x64);
+
+    NodeFile {
+        version: VERSION,
+        flags: 3,
+        entry: 1,
+        code: asm.finish(),
+        rodata: vec![1, 2, 3],
+        strings: vec!["main".into(), "src/main.krm".into()],
+        symbols: vec![Symbol {
+            name: 0,
+            kind: SymbolKind::Function,
+            exported: true,
+            arity: 0,
+            address: 1,
+            size: 7,
+        }],
+        debug: Some(DebugInfo {
+            file: 1,
+            lines: vec![LineEntry { pc: 1, line: 2 }],
+        }),
+        ..NodeFile::default()
+    }
+}
+
+/// `bytes` with the version field of the header replaced by `version`.
+fn with_version(mut bytes: Vec<u8>, version: u16) -> Vec<u8> {
+    bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&version.to_be_bytes());
+    bytes
+}
+
+#[test]
+fn node_files_round_trip() {
+    let file = sample_file();
+    let bytes = file.to_bytes();
+    assert!(bytes.starts_with(&MAGIC));
+
+    let parsed = NodeFile::parse(&bytes).unwrap();
+    assert_eq!(parsed, file);
+    assert!(!parsed.is_legacy());
+}
+
+#[test]
+fn legacy_files_are_raw_code() {
+    let code = vec![0x26, 0x10, 0, 0, 0, 1, 0x12];
+    let parsed = NodeFile::parse(&code).unwrap();
+    assert!(parsed.is_legacy());
+    assert_eq!(parsed.code, code);
+    assert_eq!(parsed.entry, 0);
+
+    assert_eq!(
+        NodeFile::parse(br#"{ "Main": [] }"#).unwrap_err(),
+        LoadError::NotANodeFile { offset: 0 }
+    );
+    assert_eq!(
+        NodeFile::parse(&[0x10, 0, 0]).unwrap_err(),
+        LoadError::NotANodeFile { offset: 0 }
+    );
+}
+
+#[test]
+fn unsupported_versions_and_truncated_files_are_rejected() {
+    let bytes = sample_file().to_bytes();
+    for version in [0, VERSION + 1] {
+        assert_eq!(
+            NodeFile::parse(&with_version(bytes.clone(), version)).unwrap_err(),
+            LoadError::UnsupportedVersion { found: version }
+        );
+    }
+
+    assert!(matches!(
+        NodeFile::parse(&bytes[..bytes.len() - 1]).unwrap_err(),
+        LoadError::Truncated { .. }
+    ));
+    assert!(matches!(
+        NodeFile::parse(&bytes[..10]).unwrap_err(),
+        LoadError::Truncated { .. }
+    ));
+}
+
+#[test]
+fn loading_a_bad_node_file_names_the_file() {
+    let dir = temp_dir("node-file-load");
+    fs::write(dir.join("graph.json"), r#"{ "Main": [] }"#).unwrap();
+    fs::write(
+        dir.join("Main.k"),
+        with_version(sample_file().to_bytes(), 9),
+    )
+    .unwrap();
+
+    let err = VirtualMachine::new(&dir.to_string_lossy()).unwrap_err();
+    let Error::Load { path, source } = err else {
+        panic!("expected a load error, got {err}");
+    };
+    assert!(path.ends_with("Main.k"));
+    assert_eq!(source, LoadError::UnsupportedVersion { found: 9 });
+}
+
+#[test]
+fn data_section_initializes_memory() {
+    let file = NodeFile {
+        code: vec![0x26],
+        memory_size: 8,
+        data: vec![1, 2, 3],
+        ..NodeFile::default()
+    };
+    let parsed = NodeFile::parse(&file.to_bytes()).unwrap();
+    assert_eq!((parsed.memory_size, &parsed.data), (8, &file.data));
+
+    let node = NodeMachine::from_node_file("Main".into(), parsed, Limits::default()).unwrap();
+    assert_eq!(node.memory(), [1, 2, 3, 0, 0, 0, 0, 0]);
+
+    let too_large = NodeFile {
+        memory_size: 2,
+        ..file
+    };
+    assert_eq!(
+        NodeFile::parse(&too_large.to_bytes()).unwrap_err(),
+        LoadError::DataTooLarge {
+            len: 3,
+            memory_size: 2
+        }
+    );
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::{single_node_project, Asm};
+use pndm::{
+    node_file::{NodeFile, Symbol, SymbolKind},
+    profile::FunctionCounts,
+    vm::VirtualMachine,
+};
+
+const CALLS: u32 = 5;
+const DEPTH: u32 = 3;
+
+/// `main` calls `square` in a loop of `CALLS` iterations, then calls `down`,
+/// which recurses `DEPTH` times before returning.
+fn profile_project(name: &str) -> String {
+    let mut asm = Asm::new();
+    asm.label("loop")
+        .op_u32(0x22, 0)
+        .op_u32(0x10, CALLS)
+        .op(0x54)
+        .jump(0x51, "recurse")
+        .jump(0x10, "back")
+        .op_u32(0x22, 0)
+        .jump(0x5A, "square")
+        .label("back")
+        .op(0x12)
+        .op_u32(0x22, 0)
+        .op_u32(0x10, 1)
+        .op(0x30)
+        .op_u32(0x24, 0)
+        .jump(0x5A, "loop")
+        .label("recurse")
+        .jump(0x10, "halt")
+        .op_u32(0x10, DEPTH)
+        .jump(0x5A, "down")
+        .label("halt")
+        .op(0x64);
+
+    // Five instructions per call.
+    asm.label("square")
+        .op_u32(0x24, 4)
+        .op_u32(0x22, 4)
+        .op_u32(0x22, 4)
+        .op(0x34)
+        .op(0x5B);
+
+    // Nine instructions per call that recurses, four for the last.
+    asm.label("down")
+        .op_u32(0x24, 8)
+        .op_u32(0x22, 8)
+        .jump(0x51, "bottom")
+        .jump(0x10, "up")
+        .op_u32(0x22, 8)
+        .op_u32(0x10, 1)
+        .op(0x32)
+        .jump(0x5A, "down")
+        .label("up")
+        .op(0x64)
+        .label("bottom")
+        .op(0x64);
+
+    let code = asm.finish();
+    let function = |name: u32, arity: u8, address: u32, end: u32| Symbol {
+        name,
+        kind: SymbolKind::Function,
+        exported: false,
+        arity,
+        address,
+        size: end - address,
+    };
+    let main = NodeFile {
+        symbols: vec![
+            function(0, 0, 0, asm.address("square")),
+            function(1, 1, asm.address("square"), asm.address("down")),
+            function(2, 1, asm.address("down"), code.len() as u32),
+        ],
+        code,
+        memory_size: 12,
+        strings: vec!["main".into(), "square".into(), "down".into()],
+        ..NodeFile::default()
+    };
+
+    single_node_project(name, main)
+}
+
+fn counts(name: &str, self_count: u64, total: u64) -> FunctionCounts {
+    FunctionCounts {
+        name: name.to_string(),
+        self_count,
+        total,
+    }
+}
+
+#[test]
+fn profile_counts_functions_and_loops() {
+    let path = profile_project("profile-counts");
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.profile();
+    vm.execute().unwrap();
+
+    let steps = vm.node("Main").unwrap().steps();
+    let profile = vm.node("Main").unwrap().profile().unwrap();
+    assert_eq!(profile.instructions(), steps);
+
+    let square = 5 * CALLS as u64;
+    let down = 9 * DEPTH as u64 + 4;
+    assert_eq!(
+        profile.functions(),
+        vec![
+            counts("main", steps - square - down, steps),
+            counts("down", down, down),
+            counts("square", square, square),
+        ]
+    );
+
+    assert!(profile.opcodes().contains(&(0x34, CALLS as u64)));
+
+    let loops = profile.loops();
+    assert_eq!(loops.len(), 1);
+    assert_eq!(loops[0].iterations, CALLS as u64);
+
+    let collapsed = profile.collapsed("Main");
+    assert!(collapsed.contains(&format!("Main;main;square {square}\n")));
+    assert!(collapsed.contains("Main;main;down;down;down;down 4\n"));
+    let sum: u64 = collapsed
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+        .sum();
+    assert_eq!(sum, steps);
+}
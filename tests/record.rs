@@ -0,0 +1,82 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use common::{fold_project, host_counter_project, node_states, register_counter, Fold};
+use pndm::{error::Error, vm::VirtualMachine};
+
+#[test]
+fn replay_reproduces_parallel_run() {
+    let path = fold_project("replay-reproduces-parallel", Fold::Ordered);
+
+    for threads in [2, 4] {
+        for slice in [1, 7] {
+            let mut vm = VirtualMachine::new(&path).unwrap();
+            vm.set_threads(threads);
+            vm.set_slice(slice);
+            vm.record();
+            vm.execute().unwrap();
+            let log = vm.recording().unwrap();
+
+            let mut replayed = VirtualMachine::new(&path).unwrap();
+            replayed.replay(&log).unwrap();
+            assert_eq!(
+                node_states(&replayed)[0],
+                node_states(&vm)[0],
+                "{threads} threads, slice {slice}"
+            );
+        }
+    }
+}
+
+#[test]
+fn replay_detects_divergence() {
+    let path = fold_project("replay-detects-divergence", Fold::Ordered);
+
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.record();
+    vm.execute().unwrap();
+    let mut log = vm.recording().unwrap();
+
+    // Have Left (node 0) receive before Main has sent it anything.
+    let receive = log.events.iter().position(|e| e.node == 0).unwrap();
+    let event = log.events.remove(receive);
+    log.events.insert(0, event);
+
+    let mut replayed = VirtualMachine::new(&path).unwrap();
+    assert!(replayed.replay(&log).is_err());
+}
+
+#[test]
+fn replay_uses_recorded_host_results() {
+    let path = host_counter_project("replay-host");
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    let calls = register_counter(&mut vm);
+    vm.record();
+    vm.execute().unwrap();
+    let mut log = vm.recording().unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // The host is not called again, and need not even be registered.
+    let mut replayed = VirtualMachine::new(&path).unwrap();
+    replayed.replay(&log).unwrap();
+    let stack: Vec<u32> = replayed
+        .node("Main")
+        .unwrap()
+        .stack()
+        .iter()
+        .copied()
+        .collect();
+    assert_eq!(stack, vec![1, 2]);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    for event in &mut log.events {
+        event.outcome = None;
+    }
+    let mut replayed = VirtualMachine::new(&path).unwrap();
+    let err = replayed.replay(&log).unwrap_err();
+    assert!(
+        matches!(err, Error::ReplayDiverged { event: 0, .. }),
+        "{err}"
+    );
+}
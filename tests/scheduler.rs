@@ -0,0 +1,79 @@
+mod common;
+
+use common::{fold_project, write_project, Asm, Fold, FOLD_ROUNDS};
+use pndm::{node_file::NodeFile, vm::VirtualMachine};
+
+fn run(path: &str, threads: usize, slice: u64) -> (Vec<u32>, Vec<u8>) {
+    let mut vm = VirtualMachine::new(path).unwrap();
+    vm.set_slice(slice);
+    vm.set_threads(threads);
+    vm.execute().unwrap();
+
+    let main = vm.node("Main").unwrap();
+    (
+        main.stack().iter().copied().collect(),
+        main.memory().to_vec(),
+    )
+}
+
+#[test]
+fn parallel_matches_deterministic() {
+    let path = fold_project("parallel-matches-deterministic", Fold::Sum);
+    let expected: u32 = (0..FOLD_ROUNDS).map(|i| i * i + i + 1000).sum();
+
+    let (stack, memory) = run(&path, 1, 7);
+    assert_eq!(stack, vec![expected]);
+
+    for threads in [2, 4, 8] {
+        for slice in [1, 7, 1000] {
+            assert_eq!(run(&path, threads, slice), (stack.clone(), memory.clone()));
+        }
+    }
+}
+
+#[test]
+fn deterministic_scheduler_is_reproducible() {
+    let path = fold_project("deterministic-is-reproducible", Fold::Ordered);
+
+    assert_eq!(run(&path, 1, 3), run(&path, 1, 3));
+}
+
+#[test]
+fn nodes_keep_running_after_the_start_nodes_halt() {
+    // Main hands Worker a value and halts at once; Worker stores its square
+    // and then waits for a second message that never comes.
+    let mut main = Asm::new();
+    main.op_u32(0x10, 9).op_u32(0xA0, 0);
+    let mut worker = Asm::new();
+    worker
+        .op(0xA1)
+        .op_u32(0x24, 0)
+        .op_u32(0x22, 0)
+        .op_u32(0x22, 0)
+        .op(0x34)
+        .op_u32(0x24, 0)
+        .op(0xA1);
+
+    let main = NodeFile {
+        code: main.finish(),
+        strings: vec!["Worker".into()],
+        ..NodeFile::default()
+    };
+    let worker = NodeFile {
+        code: worker.finish(),
+        memory_size: 4,
+        ..NodeFile::default()
+    };
+    let dir = write_project(
+        "after-start-halts",
+        r#"{ "entry": "Main", "nodes": { "Main": ["Worker"], "Worker": {} } }"#,
+        &[("Main", main), ("Worker", worker)],
+    );
+
+    for threads in [1, 4] {
+        let mut vm = VirtualMachine::new(&dir.to_string_lossy()).unwrap();
+        vm.set_threads(threads);
+        vm.execute().unwrap();
+        assert_eq!(vm.node("Worker").unwrap().memory(), 81u32.to_be_bytes());
+    }
+}
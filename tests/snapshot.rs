@@ -0,0 +1,90 @@
+mod common;
+
+use common::{single_node_project, temp_dir, Asm};
+use pndm::{error::Error, node_file::NodeFile, snapshot::NodeSnapshot, vm::VirtualMachine};
+
+/// Fills a declared array of ten words at 0 with the squares of 0..10,
+/// counting in memory at 40.
+fn squares_file() -> NodeFile {
+    let mut asm = Asm::new();
+    asm.op_u32(0x80, 0)
+        .op_u32(4, 10)
+        .label("loop")
+        .op_u32(0x22, 40)
+        .op_u32(0x10, 10)
+        .op(0x54)
+        .jump(0x51, "end")
+        .op_u32(0x22, 40)
+        .op_u32(0x22, 40)
+        .op(0x34)
+        .op_u32(0x22, 40)
+        .op_u32(0x87, 0)
+        .op_u32(0x22, 40)
+        .op_u32(0x10, 1)
+        .op(0x30)
+        .op_u32(0x24, 40)
+        .jump(0x5A, "loop")
+        .label("end")
+        .op_u32(0x22, 36);
+
+    NodeFile {
+        code: asm.finish(),
+        memory_size: 44,
+        ..NodeFile::default()
+    }
+}
+
+fn state(vm: &VirtualMachine) -> (Vec<u32>, Vec<u8>, u64) {
+    let main = vm.node("Main").unwrap();
+    (
+        main.stack().iter().copied().collect(),
+        main.memory().to_vec(),
+        main.steps(),
+    )
+}
+
+#[test]
+fn resumed_snapshot_matches_uninterrupted_run() {
+    let path = single_node_project("snapshot-resume", squares_file());
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.execute().unwrap();
+    let expected = state(&vm);
+    assert_eq!(expected.0, vec![81]);
+
+    for steps in [1, 10, 57, expected.2 - 1] {
+        let file = temp_dir(&format!("snapshot-resume-{steps}")).join("Main.json");
+        let file = file.to_string_lossy();
+        let mut vm = VirtualMachine::new(&path).unwrap();
+        vm.execute_with_snapshot(steps, &file).unwrap();
+        assert_eq!(state(&vm), expected);
+
+        let snapshot = NodeSnapshot::read(&file).unwrap();
+        assert_eq!(snapshot.steps, steps);
+        assert_eq!(snapshot.arrays.len(), usize::from(steps >= 2));
+
+        let mut resumed = VirtualMachine::new(&path).unwrap();
+        resumed.restore_node(snapshot).unwrap();
+        resumed.execute().unwrap();
+        assert_eq!(state(&resumed), expected, "resumed at {steps}");
+    }
+}
+
+#[test]
+fn snapshots_only_resume_in_their_own_code() {
+    let path = single_node_project("snapshot-source", squares_file());
+    let file = temp_dir("snapshot-file").join("Main.json");
+    let file = file.to_string_lossy();
+    let mut vm = VirtualMachine::new(&path).unwrap();
+    vm.execute_with_snapshot(5, &file).unwrap();
+
+    let mut other = squares_file();
+    other.code.push(0x26);
+    let mut vm = VirtualMachine::new(&single_node_project("snapshot-other", other)).unwrap();
+    let err = vm
+        .restore_node(NodeSnapshot::read(&file).unwrap())
+        .unwrap_err();
+    assert!(
+        matches!(&err, Error::SnapshotMismatch { node } if node == "Main"),
+        "{err}"
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::{single_node_project, Asm};
+use pndm::{
+    error::{Error, Trap},
+    node_file::{Field, FieldKind, LoadError, NodeFile, TypeLayout},
+    vm::VirtualMachine,
+};
+
+/// `Point { x: Int, c: Char }`, with `x` at 0 and `c` at 4.
+fn point() -> TypeLayout {
+    TypeLayout {
+        name: 0,
+        size: 8,
+        fields: vec![
+            Field {
+                name: 1,
+                kind: FieldKind::Int,
+                offset: 0,
+            },
+            Field {
+                name: 2,
+                kind: FieldKind::Char,
+                offset: 4,
+            },
+        ],
+    }
+}
+
+/// Appends an instruction with a base address and a field offset.
+fn field(asm: &mut Asm, opcode: u8, base: u32, offset: u32) {
+    asm.op_u32(opcode, base);
+    for byte in offset.to_be_bytes() {
+        asm.op(byte);
+    }
+}
+
+fn struct_file(asm: &Asm) -> NodeFile {
+    NodeFile {
+        code: asm.finish(),
+        memory_size: 16,
+        strings: vec!["Point".into(), "x".into(), "c".into()],
+        types: vec![point()],
+        ..NodeFile::default()
+    }
+}
+
+fn run(name: &str, asm: &Asm) -> Result<VirtualMachine, Error> {
+    let mut vm = VirtualMachine::new(&single_node_project(name, struct_file(asm))).unwrap();
+    vm.execute()?;
+    Ok(vm)
+}
+
+#[test]
+fn fields_are_read_and_written_at_their_offsets() {
+    // Writes and reads back both fields of a Point at 8, with the base as an
+    // operand and then popped.
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 7);
+    field(&mut asm, 0x41, 8, 0);
+    asm.op_u32(0x10, b'x' as u32);
+    field(&mut asm, 0x43, 8, 4);
+    field(&mut asm, 0x40, 8, 0);
+    field(&mut asm, 0x42, 8, 4);
+    asm.op_u32(0x10, 5)
+        .op_u32(0x10, 8)
+        .op_u32(0x45, 0)
+        .op_u32(0x10, b'y' as u32)
+        .op_u32(0x10, 8)
+        .op_u32(0x47, 4)
+        .op_u32(0x10, 8)
+        .op_u32(0x44, 0)
+        .op_u32(0x10, 8)
+        .op_u32(0x46, 4);
+
+    let vm = run("struct-fields", &asm).unwrap();
+    let main = vm.node("Main").unwrap();
+    let stack: Vec<u32> = main.stack().iter().copied().collect();
+    assert_eq!(stack, vec![7, b'x' as u32, 5, b'y' as u32]);
+    assert_eq!(main.memory()[8..13], [0, 0, 0, 5, b'y']);
+    assert_eq!(main.format_struct(0, 8), "Point { x: 5, c: 'y' }");
+
+    let mut asm = Asm::new();
+    field(&mut asm, 0x40, u32::MAX, 4);
+    let err = run("struct-overflow", &asm).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Trap {
+                trap: Trap::AddressOverflow,
+                ..
+            }
+        ),
+        "{err}"
+    );
+}
+
+#[test]
+fn fields_must_fit_in_their_struct() {
+    let mut file = struct_file(&Asm::new());
+    file.types[0].fields[1].offset = 8;
+    assert_eq!(
+        NodeFile::parse(&file.to_bytes()).unwrap_err(),
+        LoadError::FieldOutOfBounds {
+            layout: "Point".into(),
+            field: "c".into()
+        }
+    );
+}
@@ -0,0 +1,268 @@
+mod common;
+
+use std::io::{self, BufReader, Read};
+
+use common::{single_node_project, single_node_project_with_graph, Asm};
+use pndm::{
+    error::{Error, Trap},
+    graph::Limits,
+    node_file::{Import, NodeFile},
+    vm::{ExecStatus, NodeMachine, VirtualMachine, DEFAULT_MEMORY_LIMIT},
+};
+
+fn main_file(asm: &Asm) -> NodeFile {
+    NodeFile {
+        code: asm.finish(),
+        memory_size: 8,
+        ..NodeFile::default()
+    }
+}
+
+fn project(name: &str, graph: &str, asm: &Asm) -> VirtualMachine {
+    VirtualMachine::new(&single_node_project_with_graph(name, graph, main_file(asm))).unwrap()
+}
+
+fn run(name: &str, asm: &Asm) -> Error {
+    VirtualMachine::new(&single_node_project(name, main_file(asm)))
+        .unwrap()
+        .execute()
+        .unwrap_err()
+}
+
+/// The trap `asm` stops with, and the pc it stopped at.
+fn trap(name: &str, asm: &Asm) -> (Trap, usize) {
+    match run(name, asm) {
+        Error::Trap { trap, pc, .. } => (trap, pc),
+        err => panic!("expected a trap, got {err}"),
+    }
+}
+
+#[test]
+fn arithmetic_and_control_flow_traps() {
+    let mut asm = Asm::new();
+    asm.op(0x12);
+    assert_eq!(trap("trap-underflow", &asm), (Trap::StackUnderflow, 1));
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x22, 6);
+    let oob = Trap::MemoryOutOfBounds { addr: 6, size: 8 };
+    assert_eq!(trap("trap-oob", &asm), (oob, 1));
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 1).op_u32(0x10, 0).op(0x36);
+    assert_eq!(trap("trap-div", &asm), (Trap::DivisionByZero, 11));
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x5A, 0);
+    assert_eq!(trap("trap-jump", &asm), (Trap::InvalidJump { addr: 0 }, 1));
+
+    // Jumping into an operand runs its bytes as instructions.
+    let mut asm = Asm::new();
+    asm.op_u32(0x5A, 7).op_u32(0x10, 0xFF00_0000);
+    let invalid = Trap::InvalidOpcode { opcode: 0xFF };
+    assert_eq!(trap("trap-opcode", &asm), (invalid, 7));
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x5A, 10).op_u32(0x10, 0x10);
+    assert_eq!(
+        trap("trap-truncated", &asm),
+        (Trap::TruncatedInstruction, 10)
+    );
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x13, u32::MAX);
+    assert_eq!(trap("trap-overflow", &asm), (Trap::AddressOverflow, 1));
+}
+
+#[test]
+fn array_traps() {
+    // A 2-element array of words at 0, indexed with 2.
+    let mut asm = Asm::new();
+    asm.op_u32(0x80, 0)
+        .op_u32(4, 2)
+        .op_u32(0x10, 2)
+        .op_u32(0x82, 0);
+    let index = Trap::IndexOutOfBounds {
+        base: 0,
+        index: 2,
+        len: 2,
+    };
+    assert_eq!(trap("trap-index", &asm).0, index);
+
+    // A 2x3 array of bytes at 0, indexed with [1][3].
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 2)
+        .op_u32(0x10, 3)
+        .op_u32(0x81, 0)
+        .op(1)
+        .op(2)
+        .op_u32(0x10, 1)
+        .op_u32(0x10, 3)
+        .op_u32(0x86, 0);
+    let dim = Trap::DimensionOutOfBounds {
+        base: 0,
+        dim: 1,
+        index: 3,
+        len: 3,
+    };
+    assert_eq!(trap("trap-dim", &asm).0, dim);
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x10, 0).op_u32(0x86, 4);
+    assert_eq!(
+        trap("trap-undeclared", &asm).0,
+        Trap::UndeclaredArray { base: 4 }
+    );
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x81, 0).op(2).op(0);
+    let size = Trap::InvalidElementSize { size: 2 };
+    assert_eq!(trap("trap-elem-size", &asm).0, size);
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x80, 0).op_u32(2, 3);
+    asm.op_u32(0x10, 0).op_u32(0x86, 0);
+    assert_eq!(trap("trap-elem-size-load", &asm).0, size);
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x80, 0).op_u32(2, 3);
+    asm.op_u32(0x10, 7).op_u32(0x10, 0).op_u32(0x8B, 0);
+    assert_eq!(trap("trap-elem-size-store", &asm).0, size);
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x80, 0).op_u32(4, 100);
+    let graph = r#"{ "nodes": { "Main": { "limits": { "memory": 64 } } } }"#;
+    let err = project("trap-memory", graph, &asm).execute().unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::MemoryLimit {
+                requested: 400,
+                limit: 64,
+                ..
+            }
+        ),
+        "{err}"
+    );
+}
+
+struct Broken;
+
+impl Read for Broken {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::other("unplugged"))
+    }
+}
+
+#[test]
+fn host_traps() {
+    let mut asm = Asm::new();
+    asm.op(0x94);
+    let mut vm = VirtualMachine::new(&single_node_project("trap-input", main_file(&asm))).unwrap();
+    vm.set_input(BufReader::new(Broken));
+    let err = vm.execute().unwrap_err();
+    assert!(
+        matches!(&err, Error::Trap { trap: Trap::InputError { message }, .. } if message == "unplugged"),
+        "{err}"
+    );
+
+    // Loading a graph refuses unresolved imports before anything runs, so
+    // only a machine stepped directly can reach the trap.
+    let mut asm = Asm::new();
+    asm.op_u32(0xB0, 0);
+    let file = NodeFile {
+        code: asm.finish(),
+        strings: vec!["missing".into()],
+        imports: vec![Import { name: 0, arity: 0 }],
+        ..NodeFile::default()
+    };
+    let mut node = NodeMachine::from_node_file("Main".into(), file, Limits::default()).unwrap();
+    let ExecStatus::Trapped(err) = node.step(10) else {
+        panic!("expected the host call to trap");
+    };
+    assert!(
+        matches!(&err, Error::Trap { trap: Trap::UnresolvedImport { function }, .. } if function == "missing"),
+        "{err}"
+    );
+}
+
+#[test]
+fn failures_map_to_exit_codes() {
+    let mut asm = Asm::new();
+    asm.op(0x12);
+    assert_eq!(run("exit-trap", &asm).exit_code(), 2);
+
+    let mut asm = Asm::new();
+    asm.op(0xA1);
+    assert_eq!(run("exit-deadlock", &asm).exit_code(), 3);
+
+    let mut spin = Asm::new();
+    spin.label("loop").jump(0x5A, "loop");
+    let graph = r#"{ "nodes": { "Main": {} }, "limits": { "instructions": 1000 } }"#;
+    let err = project("exit-instructions", graph, &spin)
+        .execute()
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::InstructionLimit {
+            node: None,
+            limit: 1000
+        }
+    ));
+    assert_eq!(err.exit_code(), 4);
+
+    let graph = r#"{ "nodes": { "Main": { "limits": { "time_ms": 20 } } } }"#;
+    let err = project("exit-time", graph, &spin).execute().unwrap_err();
+    assert!(matches!(&err, Error::TimeLimit { node: Some(node), limit_ms: 20 } if node == "Main"));
+    assert_eq!(err.exit_code(), 5);
+
+    let mut asm = Asm::new();
+    asm.op_u32(0x80, 0).op_u32(1, 100);
+    let graph = r#"{ "nodes": { "Main": { "limits": { "memory": 64 } } } }"#;
+    let err = project("exit-memory", graph, &asm).execute().unwrap_err();
+    assert_eq!(err.exit_code(), 6);
+
+    let err = VirtualMachine::new("/nonexistent/pndm-project").unwrap_err();
+    assert_eq!(err.exit_code(), 1);
+}
+
+#[test]
+fn declared_memory_is_checked_before_it_is_allocated() {
+    let main = NodeFile {
+        code: vec![0x26],
+        memory_size: 0xF000_0000,
+        ..NodeFile::default()
+    };
+    let err = VirtualMachine::new(&single_node_project("memory-declared", main)).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::MemoryLimit {
+                requested: 0xF000_0000,
+                limit: DEFAULT_MEMORY_LIMIT,
+                ..
+            }
+        ),
+        "{err}"
+    );
+
+    // A node limit given up front lets a file declare more than the default.
+    let main = NodeFile {
+        code: vec![0x26],
+        memory_size: DEFAULT_MEMORY_LIMIT + 1,
+        ..NodeFile::default()
+    };
+    let path = single_node_project("memory-raised", main.clone());
+    let node = Limits {
+        memory: Some(DEFAULT_MEMORY_LIMIT * 2),
+        ..Limits::default()
+    };
+    let vm = VirtualMachine::with_limits(&path, Limits::default(), node).unwrap();
+    assert_eq!(
+        vm.node("Main").unwrap().memory().len(),
+        main.memory_size as usize
+    );
+
+    let err = NodeMachine::from_node_file("Main".into(), main, Limits::default()).unwrap_err();
+    assert!(matches!(err, Error::MemoryLimit { .. }), "{err}");
+}